@@ -10,9 +10,8 @@ async fn main() {
     let mut socket = Socket::connect("localhost:13331").await.unwrap();
 
     let name = std::env::args()
-        .skip(1)
-        .next()
-        .unwrap_or("Anonymous".to_owned());
+        .nth(1)
+        .unwrap_or_else(|| "Anonymous".to_owned());
 
     let stdin = stdin();
     let mut stdin = FramedRead::new(stdin, LinesCodec::default());
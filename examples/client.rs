@@ -14,7 +14,7 @@ pub async fn main() {
 
     for i in 0..20000_u16 {
         socket
-            .send(Packet::ordered(&i.to_be_bytes(), None))
+            .send(Packet::ordered(i.to_be_bytes(), None))
             .await
             .unwrap();
     }
@@ -27,7 +27,7 @@ pub async fn main() {
 
     for i in 0..20000_u16 {
         socket
-            .send(Packet::reliable_unordered(&i.to_be_bytes()))
+            .send(Packet::reliable_unordered(i.to_be_bytes()))
             .await
             .unwrap();
     }
@@ -0,0 +1,593 @@
+use crate::listener::BindError;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, PrivateKey, RootCertStore, ServerName,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use thiserror::Error;
+
+//
+
+/// SHA-256 fingerprint of a DER encoded certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint(pub [u8; 32]);
+
+#[derive(Debug, Error)]
+pub enum FingerprintParseError {
+    #[error("invalid fingerprint length (expected 64 hex digits, got {0})")]
+    InvalidLength(usize),
+
+    #[error("invalid hex digit in fingerprint")]
+    InvalidDigit,
+}
+
+/// Accepts only the server certificate
+/// with the given fingerprint
+///
+/// the certificate chain and the
+/// server name are not checked
+#[derive(Debug)]
+pub struct PinnedVerifier {
+    fingerprint: Fingerprint,
+}
+
+/// Verifies the certificate chain
+/// and server name against CA roots
+///
+/// used by [`crate::socket::Socket::ca_config`], servers
+/// connected by IP address can't be verified
+pub struct CaVerifier {
+    inner: WebPkiVerifier,
+}
+
+/// Trust on first use, see [`KnownHosts::verifier`]
+///
+/// the first certificate seen for a host and port
+/// is written to the known hosts file and every
+/// later certificate for them has to match it
+///
+/// the known hosts file has one `<host>:<port> <fingerprint>`
+/// entry per line, lines starting with `#` are ignored
+#[derive(Debug)]
+pub struct KnownHosts {
+    path: PathBuf,
+    known_hosts: Mutex<HashMap<String, Fingerprint>>,
+}
+
+// the verifier only sees the server name
+struct KnownHostsVerifier {
+    known_hosts: Arc<KnownHosts>,
+    port: u16,
+}
+
+//
+
+impl Fingerprint {
+    pub fn of(cert: &Certificate) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, &cert.0[..]);
+        let mut fingerprint = [0; 32];
+        fingerprint.copy_from_slice(digest.as_ref());
+        Self(fingerprint)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = FingerprintParseError;
+
+    /// accepts 64 hex digits, optionally separated with `:`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits: Vec<u8> = s.bytes().filter(|&c| c != b':').collect();
+        if digits.len() != 64 {
+            return Err(FingerprintParseError::InvalidLength(digits.len()));
+        }
+
+        let mut fingerprint = [0; 32];
+        for (byte, pair) in fingerprint.iter_mut().zip(digits.chunks(2)) {
+            let pair =
+                std::str::from_utf8(pair).map_err(|_| FingerprintParseError::InvalidDigit)?;
+            *byte =
+                u8::from_str_radix(pair, 16).map_err(|_| FingerprintParseError::InvalidDigit)?;
+        }
+
+        Ok(Self(fingerprint))
+    }
+}
+
+impl PinnedVerifier {
+    pub fn new(fingerprint: Fingerprint) -> Self {
+        Self { fingerprint }
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        check_fingerprint(self.fingerprint, Fingerprint::of(end_entity))
+    }
+}
+
+impl CaVerifier {
+    pub fn new(roots: RootCertStore) -> Self {
+        Self {
+            inner: WebPkiVerifier::new(roots, None),
+        }
+    }
+}
+
+impl ServerCertVerifier for CaVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.inner
+            .verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            )
+            .map_err(|err| {
+                if let ServerName::DnsName(name) = server_name {
+                    let valid_for_name = webpki::EndEntityCert::try_from(&end_entity.0[..])
+                        .and_then(|cert| {
+                            let name = webpki::DnsNameRef::try_from_ascii_str(name.as_ref())
+                                .map_err(|_| webpki::Error::CertNotValidForName)?;
+                            cert.verify_is_valid_for_dns_name(name)
+                        });
+                    if valid_for_name == Err(webpki::Error::CertNotValidForName) {
+                        return server_name_error("certificate is not valid for the server name");
+                    }
+                }
+                match err {
+                    rustls::Error::UnsupportedNameType => server_name_error(err.to_string()),
+                    err => err,
+                }
+            })
+    }
+}
+
+impl KnownHosts {
+    /// loads the known hosts file
+    ///
+    /// a missing file is treated as empty
+    /// and is created on the first connection
+    pub fn new<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        let known_hosts = match fs::read_to_string(&path) {
+            Ok(file) => parse_known_hosts(&file)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            path,
+            known_hosts: Mutex::new(known_hosts),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// fingerprint currently trusted for `host` and `port`
+    pub fn known(&self, host: &str, port: u16) -> Option<Fingerprint> {
+        self.known_hosts
+            .lock()
+            .unwrap()
+            .get(&host_port(host, port))
+            .copied()
+    }
+
+    /// Verifier for servers listening on `port`, the
+    /// host is the server name the client connects to
+    ///
+    /// use it with [`crate::socket::Socket::verifier_config`],
+    /// a changed certificate fails the TLS handshake with
+    /// [`crate::socket::ConnectError::CertificateRejected`]
+    pub fn verifier(self: &Arc<Self>, port: u16) -> Arc<dyn ServerCertVerifier> {
+        Arc::new(KnownHostsVerifier {
+            known_hosts: self.clone(),
+            port,
+        })
+    }
+
+    /// trusts `fingerprint` if `host` and `port` are new
+    pub(crate) fn check(
+        &self,
+        host: &str,
+        port: u16,
+        fingerprint: Fingerprint,
+    ) -> Result<(), rustls::Error> {
+        let host = host_port(host, port);
+
+        let mut known_hosts = self.known_hosts.lock().unwrap();
+        if let Some(&known) = known_hosts.get(&host) {
+            return check_fingerprint(known, fingerprint).map(|_| ());
+        }
+
+        log::debug!("Trusting {host} ({fingerprint}) on first use");
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{host} {fingerprint}"))
+            .map_err(|err| {
+                rustls::Error::General(format!("failed to write known hosts ({err})"))
+            })?;
+        known_hosts.insert(host, fingerprint);
+
+        Ok(())
+    }
+}
+
+impl ServerCertVerifier for KnownHostsVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_owned(),
+            ServerName::IpAddress(ip) => ip.to_string(),
+            _ => return Err(rustls::Error::UnsupportedNameType),
+        };
+
+        self.known_hosts
+            .check(&host, self.port, Fingerprint::of(end_entity))
+            .map(|_| ServerCertVerified::assertion())
+    }
+}
+
+//
+
+/// Loads a certificate chain from a PEM or DER file
+///
+/// PEM files may contain the whole chain,
+/// DER files contain a single certificate
+pub fn load_cert_chain<P: AsRef<Path>>(path: P) -> Result<Vec<Certificate>, BindError> {
+    let file = fs::read(path).map_err(BindError::CertFileError)?;
+
+    let chain: Vec<Certificate> = if is_pem(&file) {
+        rustls_pemfile::certs(&mut &file[..])
+            .map_err(BindError::PemError)?
+            .into_iter()
+            .map(Certificate)
+            .collect()
+    } else {
+        vec![Certificate(file)]
+    };
+
+    if chain.is_empty() {
+        return Err(BindError::NoCertificates);
+    }
+
+    Ok(chain)
+}
+
+/// Loads a PKCS#8, PKCS#1 (RSA) or SEC1 (EC)
+/// private key from a PEM file or a DER file
+///
+/// the first key in a PEM file is used
+pub fn load_private_key<P: AsRef<Path>>(path: P) -> Result<PrivateKey, BindError> {
+    let file = fs::read(path).map_err(BindError::CertFileError)?;
+
+    if !is_pem(&file) {
+        return Ok(PrivateKey(file));
+    }
+
+    rustls_pemfile::read_all(&mut &file[..])
+        .map_err(BindError::PemError)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or(BindError::NoPrivateKey)
+}
+
+/// Self signed certificate for `localhost`
+pub fn generate_self_signed() -> Result<(Vec<Certificate>, PrivateKey), BindError> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    let cert_der = cert.serialize_der()?;
+    let priv_key = cert.serialize_private_key_der();
+
+    Ok((vec![Certificate(cert_der)], PrivateKey(priv_key)))
+}
+
+/// Loads a self signed certificate and its private key from
+/// a PEM file or generates and stores them if it doesn't exist
+pub fn load_or_generate_self_signed<P: AsRef<Path>>(
+    path: P,
+) -> Result<(Vec<Certificate>, PrivateKey), BindError> {
+    let path = path.as_ref();
+    if path.exists() {
+        return Ok((load_cert_chain(path)?, load_private_key(path)?));
+    }
+
+    log::debug!("Generating a self signed certificate to {}", path.display());
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    let cert_pem = cert.serialize_pem()?;
+    let key_pem = cert.serialize_private_key_pem();
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options
+        .open(path)
+        .and_then(|mut file| write!(file, "{cert_pem}{key_pem}"))
+        .map_err(BindError::CertFileError)?;
+
+    // signing is not deterministic, so the
+    // stored certificate has to be read back
+    Ok((load_cert_chain(path)?, load_private_key(path)?))
+}
+
+//
+
+fn is_pem(file: &[u8]) -> bool {
+    let start = file.iter().take_while(|c| c.is_ascii_whitespace()).count();
+    file[start..].starts_with(b"-----BEGIN")
+}
+
+fn check_fingerprint(
+    expected: Fingerprint,
+    found: Fingerprint,
+) -> Result<ServerCertVerified, rustls::Error> {
+    if expected == found {
+        Ok(ServerCertVerified::assertion())
+    } else {
+        Err(rustls::Error::InvalidCertificateData(format!(
+            "fingerprint mismatch, expected {expected} got {found}"
+        )))
+    }
+}
+
+/// Error for a [`ServerCertVerifier`] when the certificate is not
+/// valid for the server name, connecting then fails with
+/// [`crate::socket::ConnectError::ServerNameMismatch`]
+///
+/// any other verifier error fails with
+/// [`crate::socket::ConnectError::CertificateRejected`]
+pub fn server_name_error<S: Into<String>>(message: S) -> rustls::Error {
+    rustls::Error::General(format!("{SERVER_NAME_MISMATCH}{}", message.into()))
+}
+
+// tags `server_name_error`, the verifier error
+// only crosses QUIC as its message
+pub(crate) const SERVER_NAME_MISMATCH: &str = "[eznet server name mismatch] ";
+
+// IPv6 hosts are in brackets
+fn host_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+fn parse_known_hosts(file: &str) -> io::Result<HashMap<String, Fingerprint>> {
+    file.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+            let (host, fingerprint) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| invalid(format!("invalid known hosts entry '{line}'")))?;
+            let fingerprint = fingerprint
+                .trim()
+                .parse()
+                .map_err(|err: FingerprintParseError| invalid(err.to_string()))?;
+            Ok((host.to_owned(), fingerprint))
+        })
+        .collect()
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cert::{
+            load_cert_chain, load_or_generate_self_signed, load_private_key, parse_known_hosts,
+            Fingerprint, KnownHosts,
+        },
+        listener::{BindError, Listener},
+        socket::{ConnectError, Socket},
+    };
+    use std::{fs, sync::Arc};
+
+    #[test]
+    fn fingerprint_parse_test() {
+        let fingerprint = Fingerprint([0xab; 32]);
+        let s = fingerprint.to_string();
+        assert_eq!(s.len(), 64);
+        assert_eq!(s.parse::<Fingerprint>().unwrap(), fingerprint);
+
+        let colons = s
+            .as_bytes()
+            .chunks(2)
+            .map(|c| std::str::from_utf8(c).unwrap())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(colons.parse::<Fingerprint>().unwrap(), fingerprint);
+
+        assert!("abcd".parse::<Fingerprint>().is_err());
+        assert!("zz".repeat(32).parse::<Fingerprint>().is_err());
+    }
+
+    #[test]
+    fn known_hosts_parse_test() {
+        let a = Fingerprint([1; 32]);
+        let b = Fingerprint([2; 32]);
+        let file = format!("# comment\n\nlocalhost:13331 {a}\n[::1]:13331   {b}\n");
+
+        let known_hosts = parse_known_hosts(&file).unwrap();
+        assert_eq!(known_hosts.len(), 2);
+        assert_eq!(known_hosts["localhost:13331"], a);
+        assert_eq!(known_hosts["[::1]:13331"], b);
+
+        assert!(parse_known_hosts("localhost").is_err());
+    }
+
+    #[test]
+    fn known_hosts_test() {
+        let path = std::env::temp_dir().join(format!("eznet-known-hosts-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let (a, b) = (Fingerprint([1; 32]), Fingerprint([2; 32]));
+
+        let known_hosts = KnownHosts::new(&path).unwrap();
+        known_hosts.check("::1", 13331, a).unwrap();
+        known_hosts.check("::1", 13331, a).unwrap();
+        // another server on the same host
+        known_hosts.check("::1", 13332, b).unwrap();
+        assert!(known_hosts.check("::1", 13331, b).is_err());
+
+        let known_hosts = KnownHosts::new(&path).unwrap();
+        assert_eq!(known_hosts.known("::1", 13331), Some(a));
+        assert_eq!(known_hosts.known("::1", 13332), Some(b));
+        fs::remove_file(&path).unwrap();
+
+        let known_hosts = KnownHosts::new(path.join("missing").join("known_hosts")).unwrap();
+        assert!(known_hosts.check("localhost", 13331, a).is_err());
+    }
+
+    #[tokio::test]
+    async fn known_hosts_verifier_test() {
+        let path = std::env::temp_dir().join(format!("eznet-tofu-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let known_hosts = Arc::new(KnownHosts::new(&path).unwrap());
+
+        let listener = Listener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addrs()[0];
+        let fingerprint = listener.certificate_fingerprint().unwrap();
+        tokio::spawn(accept_all(listener));
+
+        let config = || Socket::verifier_config(known_hosts.verifier(addr.port()));
+        Socket::connect_config(addr, config()).await.unwrap();
+        assert_eq!(
+            known_hosts.known("127.0.0.1", addr.port()),
+            Some(fingerprint)
+        );
+        Socket::connect_config(addr, config()).await.unwrap();
+
+        // a different certificate for the same host and port
+        let listener = Listener::bind("127.0.0.1:0").unwrap();
+        let other = listener.local_addrs()[0];
+        tokio::spawn(accept_all(listener));
+        let config = Socket::verifier_config(known_hosts.verifier(addr.port()));
+        assert!(matches!(
+            Socket::connect_config(other, config).await,
+            Err(ConnectError::CertificateRejected(reason)) if reason.contains("fingerprint mismatch")
+        ));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    async fn accept_all(mut listener: Listener) {
+        let mut sockets = vec![];
+        while let Ok(socket) = listener.next().await {
+            sockets.push(socket);
+        }
+    }
+
+    #[test]
+    fn load_cert_test() {
+        let dir = std::env::temp_dir().join(format!("eznet-load-cert-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let cert_der = cert.serialize_der().unwrap();
+        let key_der = cert.serialize_private_key_der();
+
+        fs::write(dir.join("cert.pem"), cert.serialize_pem().unwrap()).unwrap();
+        fs::write(dir.join("key.pem"), cert.serialize_private_key_pem()).unwrap();
+        fs::write(dir.join("cert.der"), &cert_der).unwrap();
+        fs::write(dir.join("key.der"), &key_der).unwrap();
+
+        for ext in ["pem", "der"] {
+            // rcgen signs the certificate again on every serialize
+            let chain = load_cert_chain(dir.join(format!("cert.{ext}"))).unwrap();
+            assert_eq!(chain.len(), 1);
+
+            let key = load_private_key(dir.join(format!("key.{ext}"))).unwrap();
+            assert_eq!(key.0, key_der);
+        }
+
+        assert_eq!(
+            load_cert_chain(dir.join("cert.der")).unwrap()[0].0,
+            cert_der
+        );
+
+        assert!(matches!(
+            load_private_key(dir.join("cert.pem")),
+            Err(BindError::NoPrivateKey)
+        ));
+        assert!(matches!(
+            load_cert_chain(dir.join("key.pem")),
+            Err(BindError::NoCertificates)
+        ));
+        assert!(matches!(
+            load_cert_chain(dir.join("missing.pem")),
+            Err(BindError::CertFileError(_))
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn persistent_self_signed_test() {
+        let dir = std::env::temp_dir().join(format!("eznet-persistent-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("identity.pem");
+
+        let (chain_a, key_a) = load_or_generate_self_signed(&path).unwrap();
+        let (chain_b, key_b) = load_or_generate_self_signed(&path).unwrap();
+        assert_eq!(Fingerprint::of(&chain_a[0]), Fingerprint::of(&chain_b[0]));
+        assert_eq!(key_a, key_b);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{
+    attempt_all, attempt_staggered,
+    config::{FlowControl, SocketConfig},
+    filter::FilterConfig,
+    interleave,
+    listener::BindError,
+    session::SessionHandshake,
+    socket::{resolve, ConnectError, Socket, ToServerAddrs},
+    ATTEMPT_DELAY,
+};
+use quinn::{ClientConfig, Endpoint};
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Arc,
+};
+
+//
+
+/// Client endpoint shared by many sockets
+///
+/// [`Socket::connect`] binds a new UDP port for
+/// every socket, all sockets from one [`Client`]
+/// use the same port
+///
+/// cloning gives another handle to the same endpoint
+#[derive(Debug, Clone)]
+pub struct Client {
+    endpoint: Endpoint,
+    config: SocketConfig,
+    // `None` if the transport settings are from the `ClientConfig`
+    flow_control: Option<FlowControl>,
+}
+
+//
+
+impl Client {
+    /// `addr` is the local address, use
+    /// `0.0.0.0:0` or `[::]:0` for any
+    pub fn bind<A: ToSocketAddrs>(addr: A, config: ClientConfig) -> Result<Self, BindError> {
+        Self::bind_config(addr, config, SocketConfig::default(), None)
+    }
+
+    /// Like [`Client::bind`], with the queue sizes, transport
+    /// and handshake settings from `config` for every socket
+    ///
+    /// the transport settings replace the ones in `client`
+    pub fn bind_with<A: ToSocketAddrs>(
+        addr: A,
+        mut client: ClientConfig,
+        config: SocketConfig,
+    ) -> Result<Self, BindError> {
+        client.transport = Arc::new(config.transport_config());
+        let flow_control = Some(config.flow_control);
+        Self::bind_config(addr, client, config, flow_control)
+    }
+
+    fn bind_config<A: ToSocketAddrs>(
+        addr: A,
+        client: ClientConfig,
+        config: SocketConfig,
+        flow_control: Option<FlowControl>,
+    ) -> Result<Self, BindError> {
+        let addrs = addr
+            .to_socket_addrs()
+            .map_err(BindError::InvalidSocketAddress)?;
+
+        attempt_all(
+            addrs,
+            move |addr| {
+                let mut endpoint = Endpoint::client(addr)?;
+                endpoint.set_default_client_config(client.clone());
+                Ok(Self {
+                    endpoint,
+                    config: config.clone(),
+                    flow_control,
+                })
+            },
+            BindError::NoSocketAddress,
+        )
+    }
+
+    /// the server name is taken from the host part of `addr`
+    ///
+    /// see [`Socket::connect`]
+    pub async fn connect<A: ToServerAddrs>(&self, addr: A) -> Result<Socket, ConnectError> {
+        self.connect_with(addr, &self.config).await
+    }
+
+    /// Like [`Client::connect`], with the queue sizes, handshake
+    /// settings and server name from `config`
+    ///
+    /// the transport settings and the local
+    /// address are the ones of the client
+    pub async fn connect_with<A: ToServerAddrs>(
+        &self,
+        addr: A,
+        config: &SocketConfig,
+    ) -> Result<Socket, ConnectError> {
+        let server_name = &config
+            .server_name
+            .clone()
+            .unwrap_or_else(|| addr.server_name());
+        let addrs = resolve(&addr)
+            .await
+            .map_err(ConnectError::InvalidSocketAddress)?;
+
+        attempt_staggered(
+            interleave(addrs),
+            |addr| self.connect_session(addr, server_name, config),
+            ATTEMPT_DELAY,
+            ConnectError::NoSocketAddress,
+        )
+        .await
+    }
+
+    /// see [`Socket::connect_with_name`]
+    pub async fn connect_with_name(
+        &self,
+        addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<Socket, ConnectError> {
+        let config = self
+            .config
+            .clone()
+            .with_server_name(Some(server_name.to_owned()));
+        self.connect_with(addr, &config).await
+    }
+
+    /// see [`Socket::connect_with_filter`]
+    pub async fn connect_with_filter(
+        &self,
+        addr: SocketAddr,
+        server_name: &str,
+        filter: &FilterConfig,
+    ) -> Result<Socket, ConnectError> {
+        let config = self
+            .config
+            .clone()
+            .with_server_name(Some(server_name.to_owned()))
+            .with_filter(filter.clone());
+        self.connect_with(addr, &config).await
+    }
+
+    async fn connect_session(
+        &self,
+        addr: SocketAddr,
+        server_name: &str,
+        config: &SocketConfig,
+    ) -> Result<Socket, ConnectError> {
+        let mut socket = Socket::connect_endpoint(
+            self.endpoint.clone(),
+            addr,
+            server_name,
+            config,
+            SessionHandshake::Disabled,
+        )
+        .await?;
+        socket.flow_control = self.flow_control;
+        Ok(socket)
+    }
+
+    /// waits for every socket of this client
+    pub async fn wait_idle(&self) {
+        self.endpoint.wait_idle().await
+    }
+
+    pub fn local(&self) -> SocketAddr {
+        self.endpoint.local_addr().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{client::Client, listener::Listener, packet::Packet, socket::Socket};
+
+    #[tokio::test]
+    async fn shared_endpoint_test() {
+        let mut listener = Listener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addrs()[0];
+        let client = Client::bind("127.0.0.1:0", Socket::default_config()).unwrap();
+
+        let (server_a, a) = tokio::join!(listener.next(), client.connect(addr));
+        let (server_b, b) = tokio::join!(listener.next(), client.connect(addr));
+        let (_server_a, a) = (server_a.unwrap(), a.unwrap());
+        let (mut server_b, mut b) = (server_b.unwrap(), b.unwrap());
+        assert_eq!(a.local(), client.local());
+        assert_eq!(b.local(), client.local());
+
+        // dropping one socket leaves the endpoint open
+        drop(a);
+        b.send(Packet::ordered("ping", None)).await.unwrap();
+        assert_eq!(server_b.recv().await.unwrap().bytes, "ping");
+        server_b.send(Packet::ordered("pong", None)).await.unwrap();
+        assert_eq!(b.recv().await.unwrap().bytes, "pong");
+
+        let (server_c, c) = tokio::join!(listener.next(), client.connect(addr));
+        server_c.unwrap();
+        assert_eq!(c.unwrap().local(), client.local());
+    }
+}
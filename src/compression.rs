@@ -0,0 +1,312 @@
+use crate::{
+    packet::{Packet, PacketHeader},
+    wire::{self, WireError},
+};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+};
+
+//
+
+/// Compression algorithm of a packet
+///
+/// every algorithm is behind a cargo feature with the
+/// same name, without it packets are sent raw and
+/// received ones fail with [`WireError::UnsupportedCompression`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Compression {
+    #[default]
+    None,
+
+    /// fast, for frequent packets
+    Lz4,
+
+    /// smaller, for large payloads
+    Zstd,
+}
+
+/// Which packets are compressed
+///
+/// packets are only compressed with algorithms that the
+/// peer supports and sent raw if it doesn't make them smaller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionConfig {
+    /// for packets that don't pick one
+    /// with [`Packet::with_compression`]
+    pub default: Compression,
+
+    /// overrides `default` for the ordered and
+    /// sequenced packets of a `stream_id`
+    pub streams: HashMap<Option<u8>, Compression>,
+
+    /// smaller payloads are always sent raw
+    pub threshold: usize,
+}
+
+/// Packets that a socket sent compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompressionStats {
+    pub packets: u64,
+
+    /// payload bytes before compression
+    pub raw_bytes: u64,
+
+    /// payload bytes after compression
+    pub compressed_bytes: u64,
+}
+
+/// shared between the writer and the socket
+#[derive(Debug, Default)]
+pub(crate) struct CompressionCounters {
+    packets: AtomicU64,
+    raw_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+}
+
+/// encodes the packets of the writer
+#[derive(Debug)]
+pub(crate) struct Compressor {
+    config: CompressionConfig,
+    peer: u8,
+    counters: Arc<CompressionCounters>,
+}
+
+//
+
+impl Compression {
+    /// algorithms that are compiled in
+    pub const ALL: &'static [Self] = &[
+        Self::None,
+        #[cfg(feature = "lz4")]
+        Self::Lz4,
+        #[cfg(feature = "zstd")]
+        Self::Zstd,
+    ];
+
+    /// [`Compression::ALL`] as a bit set of ids
+    pub(crate) fn supported() -> u8 {
+        Self::ALL.iter().fold(0, |supported, compression| {
+            supported | 1 << compression.id()
+        })
+    }
+
+    /// id in the wire header
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Lz4 => 1,
+            Self::Zstd => 2,
+        }
+    }
+
+    /// algorithms that are not compiled in are
+    /// rejected later by [`Compression::decompress`]
+    pub(crate) fn from_id(id: u8) -> Result<Self, WireError> {
+        match id {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Lz4),
+            2 => Ok(Self::Zstd),
+            id => Err(WireError::UnsupportedCompression(id)),
+        }
+    }
+
+    /// `None` if it failed or the
+    /// algorithm is not compiled in
+    pub(crate) fn compress(self, bytes: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Self::None => Some(bytes.to_vec()),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => Some(lz4_flex::compress_prepend_size(bytes)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::bulk::compress(bytes, 0).ok(),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    /// payloads that grow larger than
+    /// `max_len` are rejected
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    pub(crate) fn decompress(self, bytes: Bytes, max_len: usize) -> Result<Bytes, WireError> {
+        match self {
+            Self::None => Ok(bytes),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => {
+                // the uncompressed length is checked before allocating
+                let len = bytes
+                    .get(..4)
+                    .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+                    .ok_or(WireError::Truncated)?;
+                if len > max_len {
+                    return Err(WireError::Decompress("payload too large".to_owned()));
+                }
+                lz4_flex::decompress(&bytes[4..], len)
+                    .map(Bytes::from)
+                    .map_err(|err| WireError::Decompress(err.to_string()))
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => {
+                use std::io::Read;
+
+                let mut payload = Vec::new();
+                zstd::stream::read::Decoder::new(&bytes[..])
+                    .and_then(|decoder| decoder.take(max_len as u64 + 1).read_to_end(&mut payload))
+                    .map_err(|err| WireError::Decompress(err.to_string()))?;
+                if payload.len() > max_len {
+                    return Err(WireError::Decompress("payload too large".to_owned()));
+                }
+                Ok(payload.into())
+            }
+            #[allow(unreachable_patterns)]
+            compression => Err(WireError::UnsupportedCompression(compression.id())),
+        }
+    }
+}
+
+impl CompressionConfig {
+    pub fn with_default(mut self, default: Compression) -> Self {
+        self.default = default;
+        self
+    }
+
+    pub fn with_stream(mut self, stream_id: Option<u8>, compression: Compression) -> Self {
+        self.streams.insert(stream_id, compression);
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            default: Compression::None,
+            streams: HashMap::new(),
+            threshold: 256,
+        }
+    }
+}
+
+impl CompressionStats {
+    /// raw bytes per compressed byte,
+    /// `1.0` if nothing was compressed
+    pub fn ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            return 1.0;
+        }
+        self.raw_bytes as f64 / self.compressed_bytes as f64
+    }
+}
+
+impl CompressionCounters {
+    pub(crate) fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            packets: self.packets.load(Ordering::Relaxed),
+            raw_bytes: self.raw_bytes.load(Ordering::Relaxed),
+            compressed_bytes: self.compressed_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Compressor {
+    /// `peer` is the bit set of algorithms the peer supports
+    pub(crate) fn new(
+        config: CompressionConfig,
+        peer: u8,
+        counters: Arc<CompressionCounters>,
+    ) -> Self {
+        Self {
+            config,
+            peer,
+            counters,
+        }
+    }
+
+    pub(crate) fn encode(&self, packet: &Packet) -> Bytes {
+        let compression = self.pick(packet);
+        if compression == Compression::None {
+            return wire::encode(packet);
+        }
+
+        match compression.compress(&packet.bytes) {
+            Some(compressed) if compressed.len() < packet.bytes.len() => {
+                let counters = &self.counters;
+                counters.packets.fetch_add(1, Ordering::Relaxed);
+                counters
+                    .raw_bytes
+                    .fetch_add(packet.bytes.len() as u64, Ordering::Relaxed);
+                counters
+                    .compressed_bytes
+                    .fetch_add(compressed.len() as u64, Ordering::Relaxed);
+
+                wire::encode_with(packet.header, compression, &compressed)
+            }
+            _ => wire::encode(packet),
+        }
+    }
+
+    fn pick(&self, packet: &Packet) -> Compression {
+        let stream = match packet.header {
+            PacketHeader::Ordered { stream_id }
+            | PacketHeader::ReliableSequenced { stream_id, .. }
+            | PacketHeader::UnreliableSequenced { stream_id, .. } => {
+                self.config.streams.get(&stream_id).copied()
+            }
+            _ => None,
+        };
+        let compression = packet
+            .compression()
+            .or(stream)
+            .unwrap_or(self.config.default);
+
+        if packet.bytes.len() < self.config.threshold || self.peer & (1 << compression.id()) == 0 {
+            Compression::None
+        } else {
+            compression
+        }
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compression::{Compression, CompressionConfig, CompressionCounters, Compressor},
+        packet::Packet,
+        wire::decode,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn compressor_test() {
+        let counters = Arc::new(CompressionCounters::default());
+        let config = CompressionConfig::default().with_threshold(16);
+        let compressor = Compressor::new(config, Compression::supported(), counters.clone());
+
+        let payload = "chunk ".repeat(100);
+        for &compression in Compression::ALL {
+            for bytes in [payload.as_str(), "tiny"] {
+                let packet = Packet::ordered(bytes, Some(2)).with_compression(compression);
+                let encoded = compressor.encode(&packet);
+                let decoded = decode(encoded.clone()).unwrap();
+                assert_eq!(decoded.header, packet.header);
+                assert_eq!(decoded.bytes, packet.bytes);
+                if compression != Compression::None && bytes.len() > 16 {
+                    assert!(encoded.len() < bytes.len());
+                }
+            }
+        }
+
+        let stats = counters.stats();
+        assert_eq!(stats.packets, Compression::ALL.len() as u64 - 1);
+        assert!(stats.ratio() >= 1.0);
+    }
+}
@@ -0,0 +1,343 @@
+use crate::{compression::CompressionConfig, filter::FilterConfig, session::SessionConfig};
+use quinn::{
+    congestion::{BbrConfig, CubicConfig, NewRenoConfig},
+    IdleTimeout, TransportConfig, VarInt,
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+//
+
+/// Keep-alive and idle timeout of a connection
+///
+/// a connection that stays idle for longer than the
+/// idle timeout is closed with
+/// [`crate::socket::DisconnectReason::TimedOut`],
+/// keep-alive keeps quiet connections open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// how often to ping an idle peer
+    ///
+    /// `None` disables keep-alive, should
+    /// be shorter than the idle timeout
+    pub keep_alive: Option<Duration>,
+
+    /// how long the peer can stay silent
+    ///
+    /// the shorter one of the two peers is used,
+    /// `None` never times out
+    pub idle_timeout: Option<Duration>,
+}
+
+/// Congestion control algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Congestion {
+    NewReno,
+
+    #[default]
+    Cubic,
+
+    /// experimental in quinn
+    Bbr,
+}
+
+/// Congestion controller and flow control windows
+///
+/// the settings of a socket are readable
+/// with [`crate::socket::Socket::flow_control`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowControl {
+    pub congestion: Congestion,
+
+    /// initial congestion window in bytes,
+    /// `None` uses the default of the controller
+    pub initial_window: Option<u64>,
+
+    /// bytes the peer can send on all streams
+    /// together before they have been read
+    pub receive_window: u64,
+
+    /// bytes that can be sent
+    /// before they are acknowledged
+    pub send_window: u64,
+}
+
+/// Queue sizes, transport and handshake
+/// settings of a [`crate::socket::Socket`]
+///
+/// see [`crate::socket::Socket::connect_with`]
+/// and [`crate::listener::Listener::bind_with`]
+#[derive(Debug, Clone)]
+pub struct SocketConfig {
+    /// packets waiting to be sent,
+    /// sending waits while it is full
+    pub send_queue: usize,
+
+    /// received packets waiting to be read,
+    /// receiving stops while it is full
+    pub recv_queue: usize,
+
+    /// how long ordered packets are
+    /// buffered before they are sent
+    pub flush_interval: Duration,
+
+    /// bytes the peer can send on one stream
+    /// before it has been read
+    pub stream_receive_window: u32,
+
+    /// bytes of received datagrams waiting to be
+    /// read, `None` disables unreliable packets
+    pub datagram_receive_buffer: Option<usize>,
+
+    /// bytes of datagrams waiting to be sent
+    pub datagram_send_buffer: usize,
+
+    /// streams the peer can have open at once,
+    /// every unordered packet uses one
+    pub max_incoming_streams: u32,
+
+    pub timeouts: Timeouts,
+
+    pub flow_control: FlowControl,
+
+    /// the filter handshake
+    pub filter: FilterConfig,
+
+    /// which sent packets are compressed
+    pub compression: CompressionConfig,
+
+    /// client side, sent with SNI and the server certificate
+    /// is verified against it, `None` takes the host part
+    /// of the address
+    pub server_name: Option<String>,
+
+    /// client side, the endpoint is bound to this to pick the
+    /// interface or the port, `None` binds to the unspecified
+    /// address and any port
+    pub local: Option<SocketAddr>,
+}
+
+/// Settings of a [`crate::listener::Listener`]
+/// and the sockets it accepts
+#[derive(Debug, Clone, Default)]
+pub struct ListenerConfig {
+    /// settings of every accepted socket
+    pub socket: SocketConfig,
+
+    /// settings of [`crate::listener::Listener::next_session`] sessions
+    pub session: SessionConfig,
+}
+
+//
+
+impl Timeouts {
+    pub fn with_keep_alive(mut self, keep_alive: Option<Duration>) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn transport_config(&self) -> TransportConfig {
+        // too long timeouts are clamped
+        let idle_timeout = self
+            .idle_timeout
+            .map(|timeout| IdleTimeout::try_from(timeout).unwrap_or_else(|_| VarInt::MAX.into()));
+
+        let mut transport = TransportConfig::default();
+        transport
+            .keep_alive_interval(self.keep_alive)
+            .max_idle_timeout(idle_timeout);
+        transport
+    }
+}
+
+impl Default for Timeouts {
+    /// quinn's defaults, no keep-alive
+    /// and a 10 second idle timeout
+    fn default() -> Self {
+        Self {
+            keep_alive: None,
+            idle_timeout: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+impl SocketConfig {
+    pub fn with_send_queue(mut self, send_queue: usize) -> Self {
+        self.send_queue = send_queue;
+        self
+    }
+
+    pub fn with_recv_queue(mut self, recv_queue: usize) -> Self {
+        self.recv_queue = recv_queue;
+        self
+    }
+
+    pub fn with_flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    pub fn with_stream_receive_window(mut self, stream_receive_window: u32) -> Self {
+        self.stream_receive_window = stream_receive_window;
+        self
+    }
+
+    pub fn with_datagram_receive_buffer(mut self, datagram_receive_buffer: Option<usize>) -> Self {
+        self.datagram_receive_buffer = datagram_receive_buffer;
+        self
+    }
+
+    pub fn with_datagram_send_buffer(mut self, datagram_send_buffer: usize) -> Self {
+        self.datagram_send_buffer = datagram_send_buffer;
+        self
+    }
+
+    pub fn with_max_incoming_streams(mut self, max_incoming_streams: u32) -> Self {
+        self.max_incoming_streams = max_incoming_streams;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_flow_control(mut self, flow_control: FlowControl) -> Self {
+        self.flow_control = flow_control;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: FilterConfig) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: CompressionConfig) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_server_name(mut self, server_name: Option<String>) -> Self {
+        self.server_name = server_name;
+        self
+    }
+
+    pub fn with_local(mut self, local: Option<SocketAddr>) -> Self {
+        self.local = local;
+        self
+    }
+
+    pub fn transport_config(&self) -> TransportConfig {
+        let mut transport = self.timeouts.transport_config();
+        transport
+            .stream_receive_window(self.stream_receive_window.into())
+            .datagram_receive_buffer_size(self.datagram_receive_buffer)
+            .datagram_send_buffer_size(self.datagram_send_buffer)
+            .max_concurrent_uni_streams(self.max_incoming_streams.into());
+        self.flow_control.apply(&mut transport);
+        transport
+    }
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        // quinn defaults for the transport
+        Self {
+            send_queue: 256,
+            recv_queue: 256,
+            flush_interval: Duration::from_millis(1),
+            stream_receive_window: 1_250_000,
+            datagram_receive_buffer: Some(1_250_000),
+            datagram_send_buffer: 1024 * 1024,
+            max_incoming_streams: 100,
+            timeouts: Timeouts::default(),
+            flow_control: FlowControl::default(),
+            filter: FilterConfig::default(),
+            compression: CompressionConfig::default(),
+            server_name: None,
+            local: None,
+        }
+    }
+}
+
+impl ListenerConfig {
+    pub fn with_socket(mut self, socket: SocketConfig) -> Self {
+        self.socket = socket;
+        self
+    }
+
+    pub fn with_session(mut self, session: SessionConfig) -> Self {
+        self.session = session;
+        self
+    }
+}
+
+impl FlowControl {
+    pub fn with_congestion(mut self, congestion: Congestion) -> Self {
+        self.congestion = congestion;
+        self
+    }
+
+    pub fn with_initial_window(mut self, initial_window: Option<u64>) -> Self {
+        self.initial_window = initial_window;
+        self
+    }
+
+    pub fn with_receive_window(mut self, receive_window: u64) -> Self {
+        self.receive_window = receive_window;
+        self
+    }
+
+    pub fn with_send_window(mut self, send_window: u64) -> Self {
+        self.send_window = send_window;
+        self
+    }
+
+    fn apply(&self, transport: &mut TransportConfig) {
+        // too large windows are clamped
+        let receive_window = VarInt::from_u64(self.receive_window).unwrap_or(VarInt::MAX);
+        transport
+            .receive_window(receive_window)
+            .send_window(self.send_window);
+
+        match self.congestion {
+            Congestion::NewReno => {
+                let mut config = NewRenoConfig::default();
+                if let Some(window) = self.initial_window {
+                    config.initial_window(window);
+                }
+                transport.congestion_controller_factory(Arc::new(config))
+            }
+            Congestion::Cubic => {
+                let mut config = CubicConfig::default();
+                if let Some(window) = self.initial_window {
+                    config.initial_window(window);
+                }
+                transport.congestion_controller_factory(Arc::new(config))
+            }
+            Congestion::Bbr => {
+                let mut config = BbrConfig::default();
+                if let Some(window) = self.initial_window {
+                    config.initial_window(window);
+                }
+                transport.congestion_controller_factory(Arc::new(config))
+            }
+        };
+    }
+}
+
+impl Default for FlowControl {
+    fn default() -> Self {
+        // quinn defaults
+        Self {
+            congestion: Congestion::Cubic,
+            initial_window: None,
+            receive_window: VarInt::MAX.into_inner(),
+            send_window: 10_000_000,
+        }
+    }
+}
@@ -1,9 +1,19 @@
-use crate::VERSION;
+use crate::{
+    compression::Compression,
+    packet::IntoBytes,
+    session::{SessionGrant, SessionHandshake, SessionRequest},
+    version::Version,
+    wire::WIRE_VERSION,
+    VERSION,
+};
 use bytes::Bytes;
-use futures::{SinkExt, StreamExt};
-use quinn::{Connection, ConnectionError, IncomingUniStreams, WriteError};
+use futures::{future::BoxFuture, Future, FutureExt, SinkExt, StreamExt};
+use quinn::{
+    Connection, ConnectionError, IncomingUniStreams, ReadError, RecvStream, SendStream, VarInt,
+    WriteError,
+};
 use serde::{Deserialize, Serialize};
-use std::{io, time::Duration};
+use std::{fmt, io, net::SocketAddr, sync::Arc, time::Duration};
 use thiserror::Error;
 use tokio::{join, select, time::sleep};
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
@@ -30,90 +40,525 @@ pub enum FilterError {
     #[error("Invalid filter packet (invalid magic bytes)")]
     InvalidPacketMagicBytes,
 
-    // TODO: 7, see README.md
-    #[error("peer is not compatible with {}", crate::VERSION)]
-    NotCompatible,
+    #[error("Invalid filter packet (missing verdict)")]
+    MissingVerdict,
+
+    #[error("invalid peer version ({0})")]
+    InvalidVersion(String),
+
+    #[error("peer version {peer} is not compatible with {local}")]
+    NotCompatible { local: Version, peer: Version },
+
+    #[error("peer wire format {peer} is not compatible with {local}")]
+    WireVersion { local: u8, peer: u8 },
+
+    #[error("rejected by the handshake hook ({0})")]
+    Rejected(String),
+
+    #[error("the session to resume has already ended")]
+    SessionExpired,
+}
+
+/// Settings for the filter handshake that runs
+/// before a [`crate::socket::Socket`] is handed out
+#[derive(Clone)]
+pub struct FilterConfig {
+    /// application or protocol id
+    ///
+    /// peers with different magic bytes are
+    /// rejected with [`FilterError::InvalidPacketMagicBytes`]
+    pub magic_bytes: u64,
+
+    /// how long to wait for each
+    /// step of the handshake
+    pub timeout: Duration,
+
+    /// sent to the peer during the handshake
+    ///
+    /// for example a login token,
+    /// protocol name or a build number
+    pub payload: Bytes,
+
+    /// inspects the client's handshake and
+    /// rejects it by returning a reason
+    ///
+    /// only used on the server side
+    pub hook: Option<HandshakeHook>,
+}
+
+/// What the peer sent during the filter handshake
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    pub remote: SocketAddr,
+    pub version: Version,
+    pub payload: Bytes,
+
+    /// algorithms the peer can decompress
+    pub(crate) compression: u8,
+}
+
+pub type HandshakeHook =
+    Arc<dyn Fn(Handshake) -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Side {
+    Client,
+    Server,
+}
+
+//
+
+impl FilterConfig {
+    pub fn with_magic_bytes(mut self, magic_bytes: u64) -> Self {
+        self.magic_bytes = magic_bytes;
+        self
+    }
+
+    /// magic bytes derived from the SHA-256 of `protocol`
+    pub fn with_protocol_name(self, protocol: &str) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, protocol.as_bytes());
+        let mut magic_bytes = [0; 8];
+        magic_bytes.copy_from_slice(&digest.as_ref()[..8]);
+        self.with_magic_bytes(u64::from_le_bytes(magic_bytes))
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_payload<B: IntoBytes>(mut self, payload: B) -> Self {
+        self.payload = payload.into_bytes();
+        self
+    }
+
+    /// `Err(reason)` rejects the client and the
+    /// client sees the reason in its connect error
+    pub fn with_hook<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(Handshake) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.hook = Some(Arc::new(move |handshake| hook(handshake).boxed()));
+        self
+    }
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            magic_bytes: MAGIC_BYTES,
+            timeout: Duration::from_secs(5),
+            payload: Bytes::new(),
+            hook: None,
+        }
+    }
+}
+
+impl fmt::Debug for FilterConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilterConfig")
+            .field("magic_bytes", &self.magic_bytes)
+            .field("timeout", &self.timeout)
+            .field("payload", &self.payload)
+            .field("hook", &self.hook.as_ref().map(|_| ".."))
+            .finish()
+    }
 }
 
 //
 
-pub async fn filter_unwanted(
+/// returns what the peer sent and
+/// the session the server granted
+pub(crate) async fn filter_unwanted(
     uni_streams: &mut IncomingUniStreams,
     connection: &Connection,
-) -> Result<(), FilterError> {
-    let (a, b) = join!(send_filter_test(connection), recv_filter_test(uni_streams));
-    a?;
-    b?;
+    config: &FilterConfig,
+    side: Side,
+    session: SessionHandshake<'_>,
+) -> Result<(Handshake, Option<SessionGrant>), FilterError> {
+    let request = match session {
+        SessionHandshake::Request(request) => Some(request),
+        _ => None,
+    };
 
-    Ok(())
+    let (send, recv) = join!(
+        send_filter_test(connection, config, request),
+        recv_filter_test(uni_streams, connection, config)
+    );
+
+    match side {
+        Side::Client => {
+            let result = async {
+                // filter errors are more useful than
+                // the connection being closed by the peer
+                let ((mut recv, handshake), send) = (recv?, send?);
+
+                let (a, b) = join!(finish(send), async {
+                    let grant = recv_verdict(&mut recv, config.timeout).await?;
+                    drain(&mut recv, config.timeout).await?;
+                    Ok::<_, FilterError>(grant)
+                });
+                a?;
+                let grant = b?;
+
+                Ok((handshake, grant))
+            }
+            .await;
+
+            result.map_err(|err| rejection(&err).map_or(err, FilterError::Rejected))
+        }
+        Side::Server => {
+            let ((mut recv, handshake), mut send) = (recv?, send?);
+
+            // only read if sessions are enabled,
+            // otherwise `drain` skips the request
+            let registry = match session {
+                SessionHandshake::Grant(registry) => Some(registry),
+                _ => None,
+            };
+            let request = match registry {
+                Some(_) => recv_session_request(&mut recv, config.timeout).await?,
+                None => None,
+            };
+
+            if let Some(hook) = config.hook.as_ref() {
+                if let Err(reason) = hook(handshake.clone()).await {
+                    log::debug!("Rejected {}: {reason}", handshake.remote);
+                    connection.close(REJECTED, reason.as_bytes());
+                    return Err(FilterError::Rejected(reason));
+                }
+            }
+
+            let grant = registry.zip(request).map(|(r, request)| r.grant(request));
+            let verdict = match grant {
+                Some(Some(grant)) => FilterVerdict::Session(grant),
+                _ => FilterVerdict::Accepted,
+            };
+            send.send(encode(&verdict)).await?;
+
+            let (a, b) = join!(finish(send), drain(&mut recv, config.timeout));
+            a?;
+            b?;
+
+            // the client sees that its session was not resumed
+            match grant {
+                Some(None) => Err(FilterError::SessionExpired),
+                grant => Ok((handshake, grant.flatten())),
+            }
+        }
+    }
 }
 
-async fn send_filter_test(connection: &Connection) -> Result<(), FilterError> {
-    // time out after 5 seconds
+async fn send_filter_test(
+    connection: &Connection,
+    config: &FilterConfig,
+    request: Option<SessionRequest>,
+) -> Result<FWrite, FilterError> {
+    // time out after `config.timeout`
     // open a new stream for sending the filter test message
     let mut stream = select! {
-        timeout = filter_test_time_out() => return timeout,
+        timeout = filter_test_time_out(config.timeout) => return timeout,
         stream = connection.open_uni() => FramedWrite::new(stream?, LengthDelimitedCodec::default())
     };
 
-    stream.send(PACKET.clone()).await?;
-    stream.into_inner().finish().await?;
+    stream.send(filter_packet(config)).await?;
 
-    Ok(())
+    // older peers skip this
+    if let Some(request) = request {
+        stream.send(encode(&request)).await?;
+    }
+
+    Ok(stream)
 }
 
-async fn recv_filter_test(uni_streams: &mut IncomingUniStreams) -> Result<(), FilterError> {
-    // time out after 5 seconds
+async fn recv_filter_test(
+    uni_streams: &mut IncomingUniStreams,
+    connection: &Connection,
+    config: &FilterConfig,
+) -> Result<(FRead, Handshake), FilterError> {
+    // time out after `config.timeout`
     // open a new stream for sending the filter test message
     let mut stream = select! {
-        timeout = filter_test_time_out() => return timeout,
+        timeout = filter_test_time_out(config.timeout) => return timeout,
         Some(stream) = uni_streams.next() => FramedRead::new(stream?, LengthDelimitedCodec::default()),
     };
 
     let packet = select! {
-        timeout = filter_test_time_out() => return timeout,
+        timeout = filter_test_time_out(config.timeout) => return timeout,
         Some(packet) = stream.next() => packet?,
     };
 
-    let packet: FilterPacket = bincode::deserialize(&packet[..])?;
+    let (version, extensions) = parse_filter_packet(&packet, config.magic_bytes)?;
+    let handshake = Handshake {
+        remote: connection.remote_address(),
+        version,
+        payload: Bytes::copy_from_slice(extensions.payload),
+        compression: extensions.compression,
+    };
+
+    Ok((stream, handshake))
+}
+
+fn filter_packet(config: &FilterConfig) -> Bytes {
+    let extensions = encode(&FilterExtensions {
+        payload: &config.payload,
+        wire_version: WIRE_VERSION,
+        compression: Compression::supported(),
+    });
+    let mut packet = bincode::serialize(&FilterPacket {
+        magic_bytes: config.magic_bytes,
+        version: VERSION,
+    })
+    .unwrap();
+    bincode::serialize_into(&mut packet, &extensions[..]).unwrap();
+    packet.into()
+}
 
-    if packet.magic_bytes != MAGIC_BYTES {
+// the magic bytes and the version are checked
+// before the rest is parsed, so that peers with
+// another format get a useful error
+fn parse_filter_packet(
+    bytes: &[u8],
+    magic_bytes: u64,
+) -> Result<(Version, FilterExtensions<'_>), FilterError> {
+    let packet: FilterPacket = bincode::deserialize(bytes)?;
+
+    if packet.magic_bytes != magic_bytes {
         log::debug!("Invalid filter packet {packet:?}");
         return Err(FilterError::InvalidPacketMagicBytes);
     }
 
-    // TODO: 7, see README.md
+    let local = Version::current();
+    let peer: Version = packet
+        .version
+        .parse()
+        .map_err(|_| FilterError::InvalidVersion(packet.version.to_owned()))?;
+
+    if !local.is_compatible(&peer) {
+        log::debug!("Incompatible peer version {peer}");
+        return Err(FilterError::NotCompatible { local, peer });
+    }
+
+    let tail = &bytes[bincode::serialized_size(&packet)? as usize..];
+    let extensions: &[u8] = bincode::deserialize(tail)?;
+    let extensions: FilterExtensions = bincode::deserialize(extensions)?;
+
+    if extensions.wire_version != WIRE_VERSION {
+        log::debug!("Incompatible peer wire format {}", extensions.wire_version);
+        return Err(FilterError::WireVersion {
+            local: WIRE_VERSION,
+            peer: extensions.wire_version,
+        });
+    }
+
+    Ok((peer, extensions))
+}
+
+async fn recv_verdict(
+    stream: &mut FRead,
+    timeout: Duration,
+) -> Result<Option<SessionGrant>, FilterError> {
+    // the server might take a while
+    // to run the handshake hook
+    let verdict = select! {
+        timeout = filter_test_time_out(timeout) => return timeout,
+        verdict = stream.next() => verdict.ok_or(FilterError::MissingVerdict)??,
+    };
+
+    match bincode::deserialize(&verdict[..])? {
+        FilterVerdict::Accepted => Ok(None),
+        FilterVerdict::Session(grant) => Ok(Some(grant)),
+    }
+}
+
+// clients that don't want a session
+// finish the stream after the filter packet
+async fn recv_session_request(
+    stream: &mut FRead,
+    timeout: Duration,
+) -> Result<Option<SessionRequest>, FilterError> {
+    let request = select! {
+        timeout = filter_test_time_out(timeout) => return timeout,
+        request = stream.next() => request,
+    };
+
+    match request {
+        Some(request) => Ok(Some(bincode::deserialize(&request?[..])?)),
+        None => Ok(None),
+    }
+}
+
+// dropping a stream that is not read to the
+// end stops the peer from finishing it
+async fn drain(stream: &mut FRead, timeout: Duration) -> Result<(), FilterError> {
+    loop {
+        select! {
+            timeout = filter_test_time_out(timeout) => return timeout,
+            frame = stream.next() => match frame {
+                Some(frame) => frame?,
+                None => return Ok(()),
+            },
+        };
+    }
+}
 
+async fn finish(stream: FWrite) -> Result<(), FilterError> {
+    stream.into_inner().finish().await?;
     Ok(())
 }
 
-async fn filter_test_time_out() -> Result<(), FilterError> {
-    sleep(Duration::from_secs(5)).await;
+async fn filter_test_time_out<T>(timeout: Duration) -> Result<T, FilterError> {
+    sleep(timeout).await;
     Err(FilterError::TimedOut)
 }
 
+// rejected connections are closed by the
+// server with the reason as the close reason
+fn rejection(err: &FilterError) -> Option<String> {
+    let err = match err {
+        FilterError::ConnectionError(err) => err,
+        FilterError::WriteError(WriteError::ConnectionLost(err)) => err,
+        FilterError::IoError(err) => {
+            let err = err.get_ref()?;
+            match (err.downcast_ref(), err.downcast_ref()) {
+                (Some(ReadError::ConnectionLost(err)), _) => err,
+                (_, Some(WriteError::ConnectionLost(err))) => err,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    match err {
+        ConnectionError::ApplicationClosed(close) if close.error_code == REJECTED => {
+            Some(String::from_utf8_lossy(&close.reason).into_owned())
+        }
+        _ => None,
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> Bytes {
+    bincode::serialize(value).unwrap().into()
+}
+
 //
 
+// never changes, so that every version can
+// read the magic bytes and the version
+//
+// followed by the length prefixed `FilterExtensions`
 #[derive(Debug, Serialize, Deserialize)]
 struct FilterPacket<'a> {
     magic_bytes: u64,
     version: &'a str,
 }
 
-lazy_static::lazy_static! {
-    static ref PACKET: Bytes = {
-        bincode::serialize(&FilterPacket {
-            magic_bytes: MAGIC_BYTES,
-            version: VERSION,
-        }).unwrap().into()
-    };
+// new fields are added to the end,
+// older peers ignore them
+#[derive(Debug, Serialize, Deserialize)]
+struct FilterExtensions<'a> {
+    payload: &'a [u8],
+    wire_version: u8,
+    compression: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum FilterVerdict {
+    Accepted,
+    Session(SessionGrant),
 }
 
+type FWrite = FramedWrite<SendStream, LengthDelimitedCodec>;
+type FRead = FramedRead<RecvStream, LengthDelimitedCodec>;
+
 // just a random u64 i generated
 // Not intended filter out malicious
 // connections, just accidental
 // connections and port scanners
+//
+// default for `FilterConfig::magic_bytes`
 static MAGIC_BYTES: u64 = 0x87213c5b6657d98a;
 
-// TODO: 7, see README.md
-// static BREAKING_VERSIONS: &[(u16, u16, u16)] = &[];
+// application close code for
+// connections rejected by the hook
+pub(crate) const REJECTED: VarInt = VarInt::from_u32(1);
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        filter::{
+            filter_packet, parse_filter_packet, FilterConfig, FilterError, FilterExtensions,
+            FilterPacket, MAGIC_BYTES,
+        },
+        version::Version,
+        wire::WIRE_VERSION,
+        VERSION,
+    };
+
+    #[test]
+    fn protocol_name_test() {
+        let a = FilterConfig::default().with_protocol_name("game-a");
+        let b = FilterConfig::default().with_protocol_name("game-b");
+
+        assert_eq!(FilterConfig::default().magic_bytes, MAGIC_BYTES);
+        assert_eq!(
+            a.magic_bytes,
+            FilterConfig::default()
+                .with_protocol_name("game-a")
+                .magic_bytes
+        );
+        assert_ne!(a.magic_bytes, b.magic_bytes);
+        assert_ne!(a.magic_bytes, MAGIC_BYTES);
+    }
+
+    #[test]
+    fn filter_packet_test() {
+        let config = FilterConfig::default().with_payload("token");
+        let packet = filter_packet(&config);
+
+        let (version, extensions) = parse_filter_packet(&packet, MAGIC_BYTES).unwrap();
+        assert_eq!(version, Version::current());
+        assert_eq!(extensions.payload, b"token");
+
+        // a newer peer with another extension
+        let mut extensions = bincode::serialize(&FilterExtensions {
+            payload: b"token",
+            wire_version: WIRE_VERSION,
+            compression: 0,
+        })
+        .unwrap();
+        extensions.push(0xff);
+        let mut newer = bincode::serialize(&FilterPacket {
+            magic_bytes: MAGIC_BYTES,
+            version: VERSION,
+        })
+        .unwrap();
+        bincode::serialize_into(&mut newer, &extensions[..]).unwrap();
+        let (_, extensions) = parse_filter_packet(&newer, MAGIC_BYTES).unwrap();
+        assert_eq!(extensions.payload, b"token");
+
+        assert!(matches!(
+            parse_filter_packet(&packet, MAGIC_BYTES ^ 1),
+            Err(FilterError::InvalidPacketMagicBytes)
+        ));
+    }
+
+    #[test]
+    fn baseline_packet_test() {
+        // 0.2.0 only sent the magic bytes and the version
+        let baseline = bincode::serialize(&FilterPacket {
+            magic_bytes: MAGIC_BYTES,
+            version: "eznet-0.2.0",
+        })
+        .unwrap();
+
+        assert!(matches!(
+            parse_filter_packet(&baseline, MAGIC_BYTES),
+            Err(FilterError::NotCompatible { local, peer })
+                if local == Version::current() && peer == Version::new(0, 2, 0)
+        ));
+    }
+}
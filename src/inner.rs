@@ -1,12 +1,26 @@
 use crate::{
-    filter::filter_unwanted, packet::Packet, reader::reader_worker_job, socket::ConnectError,
+    compression::{CompressionCounters, Compressor},
+    config::{FlowControl, SocketConfig},
+    filter::{filter_unwanted, FilterError, Side},
+    packet::Packet,
+    reader::reader_worker_job,
+    session::{SessionGrant, SessionHandshake},
+    socket::{ConnectError, DisconnectReason, APPLICATION_CODES, INTERNAL_ERROR},
+    version::Version,
     writer::writer_worker_job,
 };
+use bytes::Bytes;
 use futures::future::join;
-use quinn::{Connection, Endpoint, NewConnection};
+use quinn::{Connection, ConnectionError, Endpoint, NewConnection, VarInt};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::{
-    sync::{broadcast, mpsc},
+    runtime::Handle,
+    sync::{broadcast, mpsc, oneshot},
     task::JoinHandle,
+    time::timeout,
 };
 
 //
@@ -15,39 +29,119 @@ use tokio::{
 pub struct SocketInner {
     pub(crate) endpoint: Endpoint,
     pub(crate) connection: Connection,
+    pub(crate) peer_version: Version,
+    pub(crate) peer_payload: Bytes,
+    pub(crate) session: Option<SessionGrant>,
+    pub(crate) closed: Closed,
+    pub(crate) connected_reported: bool,
+    // set by the caller that applied the transport settings
+    pub(crate) flow_control: Option<FlowControl>,
+    pub(crate) compression: Arc<CompressionCounters>,
+    // how long disconnecting waits for the peer
+    pub(crate) drain_timeout: Duration,
 
     pub(crate) channels: Option<(mpsc::Sender<Packet>, mpsc::Receiver<Packet>)>,
 
     pub(crate) write_worker: JoinHandle<()>,
     pub(crate) read_worker: JoinHandle<()>,
     pub(crate) should_stop: broadcast::Sender<()>,
+    pub(crate) drain: oneshot::Sender<()>,
+}
+
+/// Why the connection was closed, shared
+/// between the socket and its workers
+#[derive(Debug, Clone)]
+pub(crate) struct Closed {
+    connection: Connection,
+    reason: Arc<Mutex<Option<DisconnectReason>>>,
 }
 
 //
 
 impl SocketInner {
-    pub(crate) fn close(self) {
+    pub(crate) async fn close(self) {
         let Self {
             endpoint,
             connection,
             channels,
+            peer_version: _,
+            peer_payload: _,
+            session: _,
+            closed: _,
+            connected_reported: _,
+            flow_control: _,
+            compression: _,
+            drain_timeout: _,
             write_worker,
             read_worker,
             should_stop,
+            drain: _,
         } = self;
 
-        futures::executor::block_on(async move {
-            let _ = should_stop.send(());
-            let _ = join(write_worker, read_worker).await;
-            let _ = (channels, connection, endpoint);
+        let _ = should_stop.send(());
+        let _ = join(write_worker, read_worker).await;
+        let _ = (channels, connection, endpoint);
 
-            log::debug!("Closing socket");
+        log::debug!("Closing socket");
+    }
 
-            // TODO: 3, see README.md
-        });
+    /// [`SocketInner::close`] in the background
+    pub(crate) fn close_in_background(self) {
+        match Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(self.close());
+            }
+            // the workers stop on their own
+            // when they see the signal
+            Err(_) => {
+                let _ = self.should_stop.send(());
+            }
+        }
     }
 
-    pub(crate) async fn new(conn: NewConnection, endpoint: Endpoint) -> Result<Self, ConnectError> {
+    pub(crate) async fn disconnect(self, code: u32, reason: &str) {
+        let Self {
+            endpoint,
+            connection,
+            channels,
+            peer_version: _,
+            peer_payload: _,
+            session: _,
+            closed: _,
+            connected_reported: _,
+            flow_control: _,
+            compression: _,
+            drain_timeout,
+            write_worker,
+            read_worker,
+            should_stop,
+            drain,
+        } = self;
+
+        // the writer stops after the peer got everything,
+        // a dead peer never does without an idle timeout
+        let _ = drain.send(());
+        if timeout(drain_timeout, write_worker).await.is_err() {
+            log::debug!("Peer did not receive everything in time");
+        }
+
+        let error_code = VarInt::from_u64(APPLICATION_CODES + code as u64).unwrap();
+        connection.close(error_code, reason.as_bytes());
+
+        let _ = should_stop.send(());
+        let _ = read_worker.await;
+        let _ = (channels, connection, endpoint);
+
+        log::debug!("Disconnected socket ({code}: {reason})");
+    }
+
+    pub(crate) async fn new(
+        conn: NewConnection,
+        endpoint: Endpoint,
+        config: &SocketConfig,
+        side: Side,
+        session: SessionHandshake<'_>,
+    ) -> Result<Self, ConnectError> {
         let NewConnection {
             connection,
             mut uni_streams,
@@ -55,20 +149,40 @@ impl SocketInner {
             ..
         } = conn;
 
-        filter_unwanted(&mut uni_streams, &connection).await?;
+        let (handshake, session) =
+            filter_unwanted(&mut uni_streams, &connection, &config.filter, side, session)
+                .await
+                .map_err(|err| match err {
+                    FilterError::Rejected(reason) => ConnectError::Rejected(reason),
+                    err => err.into(),
+                })?;
 
-        // TODO: 4, see README.md
-        let (worker_send, recv) = mpsc::channel(256);
-        let (send, worker_recv) = mpsc::channel(256);
+        let (worker_send, recv) = mpsc::channel(config.recv_queue.max(1));
+        let (send, worker_recv) = mpsc::channel(config.send_queue.max(1));
 
         let (should_stop, worker_should_stop_1) = broadcast::channel(1);
         let worker_should_stop_2 = worker_should_stop_1.resubscribe();
+        let (drain, worker_drain) = oneshot::channel();
+        let closed = Closed {
+            connection: connection.clone(),
+            reason: Default::default(),
+        };
+        let compression = Arc::new(CompressionCounters::default());
+        let compressor = Compressor::new(
+            config.compression.clone(),
+            handshake.compression,
+            compression.clone(),
+        );
 
         // spawn writer worker
         let write_worker = tokio::spawn(writer_worker_job(
             connection.clone(),
             worker_recv,
             worker_should_stop_1,
+            worker_drain,
+            closed.clone(),
+            config.flush_interval,
+            compressor,
         ));
 
         // spawn reader worker
@@ -77,17 +191,56 @@ impl SocketInner {
             datagrams,
             worker_send,
             worker_should_stop_2,
+            closed.clone(),
         ));
 
         Ok(Self {
             endpoint,
             connection,
+            peer_version: handshake.version,
+            peer_payload: handshake.payload,
+            session,
+            closed,
+            connected_reported: false,
+            flow_control: None,
+            compression,
+            drain_timeout: config.filter.timeout,
 
             channels: Some((send, recv)),
 
             write_worker,
             read_worker,
             should_stop,
+            drain,
         })
     }
 }
+
+impl Closed {
+    pub(crate) fn reason(&self) -> Option<DisconnectReason> {
+        self.reason.lock().unwrap().clone()
+    }
+
+    /// the connection was closed by the peer or
+    /// lost, local closes are not reported
+    pub(crate) fn lost(&self, err: &ConnectionError) {
+        if *err != ConnectionError::LocallyClosed {
+            self.record(err.clone().into());
+        }
+    }
+
+    /// closes the connection because of a local
+    /// error, the peer sees `reason` as the message
+    pub(crate) fn fail(&self, reason: DisconnectReason) {
+        // recorded first, closing wakes up the
+        // workers that would record a lost connection
+        let message = reason.to_string();
+        self.record(reason);
+        self.connection.close(INTERNAL_ERROR, message.as_bytes());
+    }
+
+    // the first reason wins
+    fn record(&self, reason: DisconnectReason) {
+        self.reason.lock().unwrap().get_or_insert(reason);
+    }
+}
@@ -1,15 +1,26 @@
 pub use bytes;
-use futures::Future;
+use futures::{stream::FuturesUnordered, Future, StreamExt};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{select, time::sleep};
 
 //
 
+pub mod cert;
+pub mod client;
+pub mod compression;
+pub mod config;
+pub mod filter;
 pub mod listener;
 pub mod packet;
+pub mod reconnect;
+pub mod session;
 pub mod socket;
+pub mod typed;
+pub mod version;
+pub mod wire;
 
 //
 
-mod filter;
 mod inner;
 mod reader;
 mod writer;
@@ -43,23 +54,62 @@ macro_rules! unwrap_or {
 
 //
 
-pub(crate) async fn attempt_all_async<I, A, B, F: FnMut(I::Item) -> Fut, Fut>(
+/// RFC 8305 connection attempt delay
+pub(crate) const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// starts the next attempt after `delay` or as soon as
+/// the previous one fails, the attempts run in parallel
+///
+/// the first success wins, the others are dropped
+pub(crate) async fn attempt_staggered<I, A, B, F: FnMut(I::Item) -> Fut, Fut>(
     iter: I,
     mut f: F,
+    delay: Duration,
     empty: B,
 ) -> Result<A, B>
 where
-    I: Iterator,
+    I: IntoIterator,
     Fut: Future<Output = Result<A, B>>,
 {
+    let mut iter = iter.into_iter();
+    let mut running = FuturesUnordered::new();
     let mut last_err = empty;
-    for item in iter {
-        match f(item).await {
-            Ok(socket) => return Ok(socket),
-            Err(err) => last_err = err,
+    loop {
+        if running.is_empty() {
+            match iter.next() {
+                Some(item) => running.push(f(item)),
+                None => return Err(last_err),
+            }
+        }
+
+        select! {
+            Some(result) = running.next() => match result {
+                Ok(ok) => return Ok(ok),
+                Err(err) => {
+                    last_err = err;
+                    running.extend(iter.next().map(&mut f));
+                }
+            },
+            _ = sleep(delay) => running.extend(iter.next().map(&mut f)),
         }
     }
-    Err(last_err)
+}
+
+/// alternates between the address families,
+/// starting with the family of the first address
+pub(crate) fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (first, second): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    let mut interleaved = Vec::with_capacity(first.len() + second.len());
+    while first.len() + second.len() != 0 {
+        interleaved.extend(first.next());
+        interleaved.extend(second.next());
+    }
+    interleaved
 }
 
 pub(crate) fn attempt_all<I, A, B, F: FnMut(I::Item) -> Result<A, B>>(
@@ -79,3 +129,77 @@ where
     }
     Err(last_err)
 }
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::{attempt_staggered, interleave};
+    use std::{future::pending, net::SocketAddr, time::Duration};
+    use tokio::time::{sleep, Instant};
+
+    #[test]
+    fn interleave_test() {
+        let addrs: Vec<SocketAddr> = [
+            "[::1]:1",
+            "[::2]:1",
+            "[::3]:1",
+            "127.0.0.1:1",
+            "127.0.0.2:1",
+        ]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+        let interleaved: Vec<String> = interleave(addrs).iter().map(|a| a.to_string()).collect();
+        assert_eq!(
+            interleaved,
+            [
+                "[::1]:1",
+                "127.0.0.1:1",
+                "[::2]:1",
+                "127.0.0.2:1",
+                "[::3]:1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn attempt_staggered_test() {
+        let delay = Duration::from_millis(50);
+
+        // the first attempt hangs, the second starts after the delay
+        let start = Instant::now();
+        let result = attempt_staggered(
+            [0, 1, 2],
+            |i| async move {
+                match i {
+                    0 => pending().await,
+                    1 => Ok(i),
+                    _ => Err(i),
+                }
+            },
+            delay,
+            -1,
+        )
+        .await;
+        assert_eq!(result, Ok(1));
+        assert!(start.elapsed() >= delay);
+
+        // failures start the next attempt right away
+        let start = Instant::now();
+        let result = attempt_staggered(
+            [0, 1, 2],
+            |i| async move {
+                if i == 2 {
+                    sleep(Duration::from_millis(1)).await;
+                }
+                Err::<(), _>(i)
+            },
+            delay,
+            -1,
+        )
+        .await;
+        assert_eq!(result, Err(2));
+        assert!(start.elapsed() < delay);
+    }
+}
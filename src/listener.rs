@@ -1,21 +1,43 @@
 use crate::{
-    attempt_all,
+    cert::{
+        generate_self_signed, load_cert_chain, load_or_generate_self_signed, load_private_key,
+        Fingerprint,
+    },
+    config::{FlowControl, ListenerConfig, SocketConfig, Timeouts},
+    filter::{FilterConfig, Side},
+    reconnect::{Reconnect, ReconnectingSocket, Session},
+    session::{SessionConfig, SessionHandshake, SessionRegistry},
     socket::{ConnectError, Socket},
 };
-use futures::StreamExt;
-use quinn::{Endpoint, Incoming, ServerConfig};
-use rustls::Certificate;
+use futures::{
+    future::poll_fn,
+    stream::{select_all, BoxStream, FuturesUnordered, SelectAll},
+    Stream, StreamExt,
+};
+use quinn::{Connecting, Endpoint, Incoming, ServerConfig};
+use rustls::{server::ClientCertVerifier, Certificate, PrivateKey};
 use std::{
-    io,
+    fs, io,
     net::{SocketAddr, ToSocketAddrs},
+    panic,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
 };
 use thiserror::Error;
+use tokio::{task::JoinHandle, time::sleep};
 
 //
 
 pub struct Listener {
-    endpoint: Endpoint,
-    incoming: Incoming,
+    tls: Arc<Tls>,
+    incoming: SelectAll<BoxStream<'static, (Endpoint, Connecting)>>,
+    socket_config: SocketConfig,
+    sessions: Arc<SessionRegistry>,
+    session_config: SessionConfig,
+    handshakes: FuturesUnordered<JoinHandle<Result<Socket, ConnectError>>>,
 }
 
 #[derive(Debug, Error)]
@@ -34,47 +56,754 @@ pub enum BindError {
 
     #[error("self signed cert error ({0})")]
     SelfSignedCertError(#[from] rcgen::RcgenError),
+
+    #[error("failed to read certificate or key file ({0})")]
+    CertFileError(io::Error),
+
+    #[error("invalid pem file ({0})")]
+    PemError(io::Error),
+
+    #[error("no certificates in certificate file")]
+    NoCertificates,
+
+    #[error("no private key in key file")]
+    NoPrivateKey,
+
+    #[error(
+        "the certificate of a server config set with from_config or set_config can't be replaced"
+    )]
+    CustomConfig,
+}
+
+// the endpoints and their TLS config, the
+// certificate watcher only holds a weak reference
+struct Tls {
+    endpoints: Vec<Endpoint>,
+    config: Mutex<ServerConfig>,
+    fingerprint: Mutex<Option<Fingerprint>>,
+    // builds the crypto config for a new certificate,
+    // `None` if the server config was not built here
+    crypto: Mutex<Option<CryptoBuilder>>,
+    // of the transport config, `None` if
+    // the server config was not built here
+    flow_control: Mutex<Option<FlowControl>>,
 }
 
+type CryptoBuilder =
+    Box<dyn Fn(Vec<Certificate>, PrivateKey) -> Result<rustls::ServerConfig, BindError> + Send>;
+
 //
 
 impl Listener {
+    /// Listens on every address `addr` resolves to
+    ///
+    /// see [`Listener::from_config_all`]
     pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, BindError> {
-        let config = Self::default_config()?;
-        let addrs = addr
-            .to_socket_addrs()
-            .map_err(BindError::InvalidSocketAddress)?;
+        let (cert_chain, priv_key) = generate_self_signed()?;
+        Self::bind_cert(addr, cert_chain, priv_key)
+    }
 
-        attempt_all(
-            addrs,
-            move |addr| Self::from_config(addr, config.clone()),
-            BindError::NoSocketAddress,
-        )
+    /// Certificate chain and private key loaded from PEM or DER files
+    pub fn bind_with_cert<A: ToSocketAddrs, C: AsRef<Path>, K: AsRef<Path>>(
+        addr: A,
+        cert_path: C,
+        key_path: K,
+    ) -> Result<Self, BindError> {
+        let cert_chain = load_cert_chain(cert_path)?;
+        let priv_key = load_private_key(key_path)?;
+        Self::bind_cert(addr, cert_chain, priv_key)
+    }
+
+    /// Self signed certificate that is generated
+    /// on the first run and stored in `path`
+    ///
+    /// later runs reuse it, so clients can
+    /// pin [`Listener::certificate_fingerprint`]
+    pub fn bind_persistent<A: ToSocketAddrs, P: AsRef<Path>>(
+        addr: A,
+        path: P,
+    ) -> Result<Self, BindError> {
+        let (cert_chain, priv_key) = load_or_generate_self_signed(path)?;
+        Self::bind_cert(addr, cert_chain, priv_key)
+    }
+
+    pub fn bind_cert<A: ToSocketAddrs>(
+        addr: A,
+        cert_chain: Vec<Certificate>,
+        priv_key: PrivateKey,
+    ) -> Result<Self, BindError> {
+        Self::bind_crypto(addr, cert_chain, priv_key, Box::new(server_crypto))
+    }
+
+    /// Like [`Listener::client_auth_config`], the client
+    /// verifier is kept when the certificate is replaced
+    pub fn bind_client_auth<A: ToSocketAddrs>(
+        addr: A,
+        cert_chain: Vec<Certificate>,
+        priv_key: PrivateKey,
+        client_verifier: Arc<dyn ClientCertVerifier>,
+    ) -> Result<Self, BindError> {
+        let crypto = move |cert_chain, priv_key| {
+            client_auth_crypto(cert_chain, priv_key, client_verifier.clone())
+        };
+        Self::bind_crypto(addr, cert_chain, priv_key, Box::new(crypto))
+    }
+
+    fn bind_crypto<A: ToSocketAddrs>(
+        addr: A,
+        cert_chain: Vec<Certificate>,
+        priv_key: PrivateKey,
+        crypto: CryptoBuilder,
+    ) -> Result<Self, BindError> {
+        let fingerprint = cert_chain.first().map(Fingerprint::of);
+        let config = ServerConfig::with_crypto(Arc::new(crypto(cert_chain, priv_key)?));
+        let listener = Self::from_config_all(addr, config)?;
+        // the transport settings are quinn's defaults
+        *listener.tls.flow_control.lock().unwrap() = Some(FlowControl::default());
+        *listener.tls.fingerprint.lock().unwrap() = fingerprint;
+        *listener.tls.crypto.lock().unwrap() = Some(crypto);
+        Ok(listener)
     }
 
     /// Self signed certificate
     pub fn default_config() -> Result<ServerConfig, BindError> {
-        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
-        let cert_der = cert.serialize_der()?;
-        let priv_key = cert.serialize_private_key_der();
-        let priv_key = rustls::PrivateKey(priv_key);
-        let cert_chain = vec![Certificate(cert_der)];
+        let (cert_chain, priv_key) = generate_self_signed()?;
+        single_cert_config(cert_chain, priv_key)
+    }
+
+    /// Self signed certificate that is generated
+    /// on the first run and stored in `path`
+    pub fn persistent_config<P: AsRef<Path>>(path: P) -> Result<ServerConfig, BindError> {
+        let (cert_chain, priv_key) = load_or_generate_self_signed(path)?;
+        single_cert_config(cert_chain, priv_key)
+    }
+
+    /// Certificate chain and private key loaded from PEM or DER files
+    pub fn cert_config<C: AsRef<Path>, K: AsRef<Path>>(
+        cert_path: C,
+        key_path: K,
+    ) -> Result<ServerConfig, BindError> {
+        let cert_chain = load_cert_chain(cert_path)?;
+        let priv_key = load_private_key(key_path)?;
+
+        single_cert_config(cert_chain, priv_key)
+    }
 
-        Ok(ServerConfig::with_single_cert(cert_chain, priv_key)?)
+    /// Requires clients to authenticate with a certificate
+    /// accepted by `client_verifier`, for example
+    /// [`rustls::server::AllowAnyAuthenticatedClient`]
+    ///
+    /// the client certificate chain is available
+    /// from [`Socket::peer_identity`]
+    ///
+    /// use [`Listener::bind_client_auth`] to
+    /// replace the certificate later
+    pub fn client_auth_config(
+        cert_chain: Vec<Certificate>,
+        priv_key: PrivateKey,
+        client_verifier: Arc<dyn ClientCertVerifier>,
+    ) -> Result<ServerConfig, BindError> {
+        let crypto = client_auth_crypto(cert_chain, priv_key, client_verifier)?;
+        Ok(ServerConfig::with_crypto(Arc::new(crypto)))
+    }
+
+    /// Like [`Listener::from_config_all`], with the queue sizes,
+    /// transport, handshake and session settings from `config`
+    ///
+    /// the transport settings replace the ones in `server`
+    pub fn bind_with<A: ToSocketAddrs>(
+        addr: A,
+        server: ServerConfig,
+        config: ListenerConfig,
+    ) -> Result<Self, BindError> {
+        let mut listener = Self::from_config_all(addr, server)?;
+        listener.set_listener_config(config);
+        Ok(listener)
     }
 
     pub fn from_config(addr: SocketAddr, config: ServerConfig) -> Result<Self, BindError> {
-        let (endpoint, incoming) = Endpoint::server(config, addr)?;
-        Ok(Self { endpoint, incoming })
+        let endpoint = Endpoint::server(config.clone(), addr)?;
+        Ok(Self::from_endpoints(vec![endpoint], config))
+    }
+
+    /// Listens on every address `addr` resolves to,
+    /// like both `::1` and `127.0.0.1` for `localhost`
+    ///
+    /// addresses that fail to bind are skipped,
+    /// with port 0 all of them share one free port
+    pub fn from_config_all<A: ToSocketAddrs>(
+        addr: A,
+        config: ServerConfig,
+    ) -> Result<Self, BindError> {
+        let addrs = addr
+            .to_socket_addrs()
+            .map_err(BindError::InvalidSocketAddress)?;
+
+        let mut endpoints: Vec<(Endpoint, Incoming)> = vec![];
+        let mut last_err = BindError::NoSocketAddress;
+        for mut addr in addrs {
+            if let (0, Some((first, _))) = (addr.port(), endpoints.first()) {
+                addr.set_port(first.local_addr()?.port());
+            }
+
+            match Endpoint::server(config.clone(), addr) {
+                Ok(endpoint) => endpoints.push(endpoint),
+                Err(err) => {
+                    log::debug!("Failed to bind {addr}: {err}");
+                    last_err = err.into();
+                }
+            }
+        }
+
+        if endpoints.is_empty() {
+            return Err(last_err);
+        }
+        Ok(Self::from_endpoints(endpoints, config))
+    }
+
+    fn from_endpoints(endpoints: Vec<(Endpoint, Incoming)>, config: ServerConfig) -> Self {
+        // incoming connections of all endpoints, tagged with their endpoint
+        let (endpoints, incoming): (Vec<_>, Vec<_>) = endpoints
+            .into_iter()
+            .map(|(endpoint, incoming)| {
+                let tag = endpoint.clone();
+                let incoming = incoming.map(move |connecting| (tag.clone(), connecting));
+                (endpoint, incoming.boxed())
+            })
+            .unzip();
+
+        Self {
+            tls: Arc::new(Tls {
+                endpoints,
+                config: Mutex::new(config),
+                fingerprint: Default::default(),
+                crypto: Default::default(),
+                flow_control: Default::default(),
+            }),
+            incoming: select_all(incoming),
+            socket_config: Default::default(),
+            sessions: Default::default(),
+            session_config: Default::default(),
+            handshakes: Default::default(),
+        }
+    }
+
+    /// Every address this listener is bound to
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.tls
+            .endpoints
+            .iter()
+            .filter_map(|endpoint| endpoint.local_addr().ok())
+            .collect()
+    }
+
+    /// SHA-256 fingerprint of the server certificate
+    ///
+    /// `None` if the config was set with
+    /// [`Listener::from_config`] or [`Listener::set_config`]
+    pub fn certificate_fingerprint(&self) -> Option<Fingerprint> {
+        *self.tls.fingerprint.lock().unwrap()
+    }
+
+    /// Sets the payload sent to clients and the
+    /// hook that accepts or rejects new clients
+    pub fn set_filter(&mut self, filter: FilterConfig) {
+        self.socket_config.filter = filter;
+    }
+
+    /// Replay buffer and resume timeout
+    /// of new [`Listener::next_session`] sessions
+    pub fn set_session_config(&mut self, config: SessionConfig) {
+        self.session_config = config;
+    }
+
+    /// Queue sizes, transport, handshake and session
+    /// settings of new connections, see [`Listener::bind_with`]
+    ///
+    /// the certificate is kept
+    pub fn set_listener_config(&mut self, config: ListenerConfig) {
+        let mut server = self.tls.config.lock().unwrap();
+        server.transport = Arc::new(config.socket.transport_config());
+        self.tls.set_config(&server);
+        drop(server);
+        *self.tls.flow_control.lock().unwrap() = Some(config.socket.flow_control);
+
+        self.socket_config = config.socket;
+        self.session_config = config.session;
+    }
+
+    /// Replaces the server config used for new connections
+    ///
+    /// already connected sockets keep working
+    pub fn set_config(&self, config: ServerConfig) {
+        self.tls.set_config(&config);
+        *self.tls.config.lock().unwrap() = config;
+        *self.tls.fingerprint.lock().unwrap() = None;
+        *self.tls.crypto.lock().unwrap() = None;
+        *self.tls.flow_control.lock().unwrap() = None;
+    }
+
+    /// Replaces the keep-alive and idle
+    /// timeout used for new connections
+    ///
+    /// the other transport settings are
+    /// taken from the [`SocketConfig`]
+    pub fn set_timeouts(&self, timeouts: Timeouts) {
+        let transport = self.socket_config.clone().with_timeouts(timeouts);
+        let mut config = self.tls.config.lock().unwrap();
+        config.transport = Arc::new(transport.transport_config());
+        self.tls.set_config(&config);
+        *self.tls.flow_control.lock().unwrap() = Some(transport.flow_control);
+    }
+
+    /// Replaces the certificate used for new connections
+    ///
+    /// the client verifier and the other settings are kept,
+    /// already connected sockets keep working
+    ///
+    /// fails with [`BindError::CustomConfig`] if the server config
+    /// came from [`Listener::from_config`] or [`Listener::set_config`]
+    pub fn set_cert(
+        &self,
+        cert_chain: Vec<Certificate>,
+        priv_key: PrivateKey,
+    ) -> Result<(), BindError> {
+        self.tls.set_cert(cert_chain, priv_key)
+    }
+
+    /// Reloads the certificate used for new
+    /// connections from PEM or DER files
+    ///
+    /// already connected sockets keep working
+    pub fn reload_cert<C: AsRef<Path>, K: AsRef<Path>>(
+        &self,
+        cert_path: C,
+        key_path: K,
+    ) -> Result<(), BindError> {
+        self.set_cert(load_cert_chain(cert_path)?, load_private_key(key_path)?)
+    }
+
+    /// Polls the certificate and key files every `interval`
+    /// and reloads them with [`Listener::reload_cert`]
+    /// when either of them is modified
+    ///
+    /// failed reloads are logged and the old certificate is kept,
+    /// the task stops when the listener is dropped or aborted
+    pub fn watch_cert<C: Into<PathBuf>, K: Into<PathBuf>>(
+        &self,
+        cert_path: C,
+        key_path: K,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let (cert_path, key_path) = (cert_path.into(), key_path.into());
+        let tls = Arc::downgrade(&self.tls);
+
+        tokio::spawn(async move {
+            let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+            let mut last = (modified(&cert_path), modified(&key_path));
+
+            loop {
+                sleep(interval).await;
+
+                let tls = match tls.upgrade() {
+                    Some(tls) => tls,
+                    None => break,
+                };
+
+                let now = (modified(&cert_path), modified(&key_path));
+                if now == last {
+                    continue;
+                }
+                last = now;
+
+                let reload = || {
+                    let cert_chain = load_cert_chain(&cert_path)?;
+                    let priv_key = load_private_key(&key_path)?;
+                    tls.set_cert(cert_chain, priv_key)
+                };
+
+                match reload() {
+                    Ok(()) => log::debug!("Reloaded certificate {}", cert_path.display()),
+                    Err(err) => log::warn!("Failed to reload certificate: {err}"),
+                }
+            }
+        })
     }
 
+    /// Next client that passed the filter
+    ///
+    /// clients whose handshake fails or that the handshake
+    /// hook rejects are skipped, so this only fails
+    /// after the endpoints stopped
+    ///
+    /// handshakes run in the background and concurrently,
+    /// clients are returned in the order they finish
+    ///
+    /// the listener is also a [`Stream`] of clients
     pub async fn next(&mut self) -> Result<Socket, ConnectError> {
-        let connecting = self
-            .incoming
-            .next()
+        self.accept(false).await
+    }
+
+    /// Next client with a session
+    ///
+    /// clients that resume their session are handed to the
+    /// existing [`ReconnectingSocket`] instead of being returned,
+    /// clients without a session are returned as
+    /// sockets that don't reconnect
+    ///
+    /// sessions only resume while this is being polled,
+    /// handshakes started by [`Listener::next`] don't
+    /// accept sessions
+    ///
+    /// see [`ReconnectingSocket::connect_session`]
+    pub async fn next_session(&mut self) -> Result<ReconnectingSocket, ConnectError> {
+        loop {
+            let sessions = self.sessions.clone();
+            let socket = self.accept(true).await?;
+
+            let grant = match socket.session {
+                Some(grant) if grant.resumed => {
+                    if !sessions.resume(grant.token, socket) {
+                        log::debug!("Session ended before it was resumed");
+                    }
+                    continue;
+                }
+                Some(grant) => grant,
+                None => {
+                    return Ok(ReconnectingSocket::spawn(
+                        socket,
+                        Reconnect::Never,
+                        None,
+                        &self.socket_config,
+                    ))
+                }
+            };
+
+            let reconnect = Reconnect::Server {
+                token: grant.token,
+                resumed: sessions.register(grant.token),
+                sessions,
+                timeout: self.session_config.resume_timeout,
+            };
+            let session = Session::new(grant.token, &self.session_config);
+
+            return Ok(ReconnectingSocket::spawn(
+                socket,
+                reconnect,
+                Some(session),
+                &self.socket_config,
+            ));
+        }
+    }
+
+    async fn accept(&mut self, sessions: bool) -> Result<Socket, ConnectError> {
+        poll_fn(|cx| self.poll_accept(cx, sessions))
             .await
-            .ok_or(ConnectError::Connect(quinn::ConnectError::EndpointStopping))?;
-        let connection = connecting.await?;
-        Socket::new(connection, self.endpoint.clone()).await
+            .unwrap_or(Err(ConnectError::Connect(
+                quinn::ConnectError::EndpointStopping,
+            )))
+    }
+
+    // starts handshakes for all new connections
+    // and returns the first one that finishes
+    fn poll_accept(
+        &mut self,
+        cx: &mut Context,
+        sessions: bool,
+    ) -> Poll<Option<Result<Socket, ConnectError>>> {
+        let stopped = loop {
+            match self.incoming.poll_next_unpin(cx) {
+                Poll::Ready(Some((endpoint, connecting))) => {
+                    let handshake = self.handshake(endpoint, connecting, sessions);
+                    self.handshakes.push(handshake);
+                }
+                Poll::Ready(None) => break true,
+                Poll::Pending => break false,
+            }
+        };
+
+        loop {
+            return match self.handshakes.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(Ok(socket)))) => Poll::Ready(Some(Ok(socket))),
+                // the filter already logged it
+                Poll::Ready(Some(Ok(Err(ConnectError::Rejected(_))))) => continue,
+                // one client failing doesn't concern the others
+                Poll::Ready(Some(Ok(Err(err)))) => {
+                    log::debug!("Handshake failed: {err}");
+                    continue;
+                }
+                Poll::Ready(Some(Err(err))) if err.is_panic() => {
+                    panic::resume_unwind(err.into_panic())
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    log::debug!("Handshake cancelled: {err}");
+                    continue;
+                }
+                Poll::Ready(None) if stopped => Poll::Ready(None),
+                _ => Poll::Pending,
+            };
+        }
+    }
+
+    fn handshake(
+        &self,
+        endpoint: Endpoint,
+        connecting: Connecting,
+        sessions: bool,
+    ) -> JoinHandle<Result<Socket, ConnectError>> {
+        let config = self.socket_config.clone();
+        let registry = self.sessions.clone();
+        let flow_control = *self.tls.flow_control.lock().unwrap();
+
+        tokio::spawn(async move {
+            let session = if sessions {
+                SessionHandshake::Grant(&registry)
+            } else {
+                SessionHandshake::Disabled
+            };
+
+            let connection = connecting.await?;
+            let mut socket =
+                Socket::new(connection, endpoint, &config, Side::Server, session).await?;
+            socket.flow_control = flow_control;
+            Ok(socket)
+        })
+    }
+}
+
+impl Stream for Listener {
+    type Item = Result<Socket, ConnectError>;
+
+    /// ends when the endpoints stop
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_accept(cx, false)
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        for handshake in self.handshakes.iter() {
+            handshake.abort();
+        }
+    }
+}
+
+//
+
+impl Tls {
+    fn set_config(&self, config: &ServerConfig) {
+        for endpoint in self.endpoints.iter() {
+            endpoint.set_server_config(Some(config.clone()));
+        }
+    }
+
+    // keeps the rest of the server config, like the timeouts
+    fn set_cert(
+        &self,
+        cert_chain: Vec<Certificate>,
+        priv_key: PrivateKey,
+    ) -> Result<(), BindError> {
+        let fingerprint = cert_chain.first().map(Fingerprint::of);
+        let crypto = match &*self.crypto.lock().unwrap() {
+            Some(crypto) => crypto(cert_chain, priv_key)?,
+            None => return Err(BindError::CustomConfig),
+        };
+
+        let mut config = self.config.lock().unwrap();
+        config.crypto = Arc::new(crypto);
+        self.set_config(&config);
+        *self.fingerprint.lock().unwrap() = fingerprint;
+        Ok(())
+    }
+}
+
+fn single_cert_config(
+    cert_chain: Vec<Certificate>,
+    priv_key: PrivateKey,
+) -> Result<ServerConfig, BindError> {
+    Ok(ServerConfig::with_crypto(Arc::new(server_crypto(
+        cert_chain, priv_key,
+    )?)))
+}
+
+// same as quinn's ServerConfig::with_single_cert
+fn server_crypto(
+    cert_chain: Vec<Certificate>,
+    priv_key: PrivateKey,
+) -> Result<rustls::ServerConfig, BindError> {
+    let mut crypto = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, priv_key)?;
+    crypto.max_early_data_size = u32::MAX;
+    Ok(crypto)
+}
+
+fn client_auth_crypto(
+    cert_chain: Vec<Certificate>,
+    priv_key: PrivateKey,
+    client_verifier: Arc<dyn ClientCertVerifier>,
+) -> Result<rustls::ServerConfig, BindError> {
+    let mut crypto = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_client_cert_verifier(client_verifier)
+        .with_single_cert(cert_chain, priv_key)?;
+    crypto.max_early_data_size = u32::MAX;
+    Ok(crypto)
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cert::{generate_self_signed, Fingerprint, PinnedVerifier},
+        config::SocketConfig,
+        filter::FilterConfig,
+        listener::Listener,
+        socket::{ConnectError, Socket},
+    };
+    use rustls::{server::AllowAnyAuthenticatedClient, RootCertStore};
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+        sync::Arc,
+        time::Duration,
+    };
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn reload_client_auth_test() {
+        let (client_chain, client_key) = generate_self_signed().unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(&client_chain[0]).unwrap();
+
+        let (cert_chain, priv_key) = generate_self_signed().unwrap();
+        let mut listener = Listener::bind_client_auth(
+            "127.0.0.1:0",
+            cert_chain,
+            priv_key,
+            AllowAnyAuthenticatedClient::new(roots),
+        )
+        .unwrap();
+        let addr = listener.local_addrs()[0];
+
+        let (cert_chain, priv_key) = generate_self_signed().unwrap();
+        let fingerprint = Fingerprint::of(&cert_chain[0]);
+        listener.set_cert(cert_chain, priv_key).unwrap();
+        assert_eq!(listener.certificate_fingerprint(), Some(fingerprint));
+
+        // failed handshakes are skipped
+        let accepted = tokio::spawn(async move { listener.next().await });
+
+        let verifier = Arc::new(PinnedVerifier::new(fingerprint));
+        let without_cert = Socket::verifier_config(verifier.clone());
+        assert!(Socket::connect_config(addr, without_cert).await.is_err());
+
+        let client_cert = client_chain[0].clone();
+        let with_cert = Socket::client_cert_config(verifier, client_chain, client_key).unwrap();
+        let _socket = Socket::connect_config(addr, with_cert).await.unwrap();
+        let server = accepted.await.unwrap().unwrap();
+        assert_eq!(server.peer_identity().unwrap()[0], client_cert);
+    }
+
+    #[tokio::test]
+    async fn bind_all_test() {
+        // `localhost` might only resolve to one of them
+        let both: [SocketAddr; 2] = [
+            (Ipv6Addr::LOCALHOST, 0).into(),
+            (Ipv4Addr::LOCALHOST, 0).into(),
+        ];
+        let listener = Listener::bind(&both[..]).unwrap();
+        let addrs = listener.local_addrs();
+        assert_eq!(addrs.len(), 2);
+        assert!(addrs[0].is_ipv6() && addrs[1].is_ipv4());
+        assert_eq!(addrs[0].port(), addrs[1].port());
+        connect_all(listener).await;
+
+        let listener = Listener::bind("localhost:0").unwrap();
+        let addrs = listener.local_addrs();
+        assert!(addrs.iter().all(|addr| addr.port() == addrs[0].port()));
+        connect_all(listener).await;
+    }
+
+    // connects to every address of `listener`
+    async fn connect_all(mut listener: Listener) {
+        for addr in listener.local_addrs() {
+            let (server, client) = tokio::join!(listener.next(), Socket::connect(addr));
+            let (server, client) = (server.unwrap(), client.unwrap());
+            assert_eq!(server.remote().is_ipv4(), addr.is_ipv4());
+            assert_eq!(server.remote().port(), client.local().port());
+        }
+    }
+
+    #[tokio::test]
+    async fn hook_reject_test() {
+        let mut listener = Listener::bind("127.0.0.1:0").unwrap();
+        listener.set_filter(FilterConfig::default().with_hook(|handshake| async move {
+            match &handshake.payload[..] {
+                b"banned" => Err("banned".to_owned()),
+                _ => Ok(()),
+            }
+        }));
+        let addr = listener.local_addrs()[0];
+        let accepted =
+            tokio::spawn(async move { listener.next().await.map(|s| s.peer_payload().clone()) });
+
+        let connect = |payload: &'static str| {
+            let config =
+                SocketConfig::default().with_filter(FilterConfig::default().with_payload(payload));
+            Socket::connect_with(addr, Socket::default_config(), config)
+        };
+
+        assert!(matches!(
+            connect("banned").await,
+            Err(ConnectError::Rejected(reason)) if reason == "banned"
+        ));
+        let _socket = connect("welcome").await.unwrap();
+        assert_eq!(&accepted.await.unwrap().unwrap()[..], b"welcome");
+    }
+
+    #[tokio::test]
+    async fn concurrent_handshake_test() {
+        let mut listener = Listener::bind("127.0.0.1:0").unwrap();
+        listener.set_filter(FilterConfig::default().with_hook(|handshake| async move {
+            if &handshake.payload[..] == b"slow" {
+                sleep(Duration::from_millis(500)).await;
+            }
+            Ok(())
+        }));
+        let addr = listener.local_addrs()[0];
+        let accepted = tokio::spawn(async move {
+            let mut payloads = Vec::new();
+            for _ in 0..2 {
+                payloads.push(listener.next().await.unwrap().peer_payload().clone());
+            }
+            payloads
+        });
+
+        let connect = |filter: FilterConfig| {
+            let config = SocketConfig::default().with_filter(filter);
+            Socket::connect_with(addr, Socket::default_config(), config)
+        };
+        let (slow, invalid, fast) = tokio::join!(
+            connect(FilterConfig::default().with_payload("slow")),
+            connect(FilterConfig::default().with_magic_bytes(1)),
+            async {
+                sleep(Duration::from_millis(100)).await;
+                connect(FilterConfig::default().with_payload("fast")).await
+            }
+        );
+        assert!(invalid.is_err());
+        let (_slow, _fast) = (slow.unwrap(), fast.unwrap());
+
+        // the failed handshake is skipped and the
+        // slow one doesn't hold back the fast one
+        let payloads = accepted.await.unwrap();
+        assert_eq!(&payloads[0][..], b"fast");
+        assert_eq!(&payloads[1][..], b"slow");
     }
 }
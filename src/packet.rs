@@ -1,3 +1,4 @@
+use crate::compression::Compression;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
@@ -52,17 +53,35 @@ impl Default for PacketHeader {
 pub struct Packet {
     pub header: PacketHeader,
     pub bytes: Bytes,
+
+    // only used when sending, see `Packet::with_compression`
+    #[serde(skip)]
+    pub(crate) compression: Option<Compression>,
 }
 
 //
 
 impl Packet {
+    /// overrides the [`crate::compression::CompressionConfig`]
+    /// of the socket for this packet
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// `None` uses the [`crate::compression::CompressionConfig`]
+    /// of the socket, received packets are always `None`
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
     /// no packets are dropped
     ///
     /// ordered
     pub fn ordered<B: IntoBytes>(bytes: B, stream_id: Option<u8>) -> Self {
         Self {
             bytes: bytes.into_bytes(),
+            compression: None,
             header: PacketHeader::Ordered { stream_id },
         }
     }
@@ -73,6 +92,7 @@ impl Packet {
     pub fn ordered_static<B: IntoStaticBytes>(bytes: B, stream_id: Option<u8>) -> Self {
         Self {
             bytes: bytes.into_bytes(),
+            compression: None,
             header: PacketHeader::Ordered { stream_id },
         }
     }
@@ -83,6 +103,7 @@ impl Packet {
     pub fn reliable_sequenced<B: IntoBytes>(bytes: B, stream_id: Option<u8>) -> Self {
         Self {
             bytes: bytes.into_bytes(),
+            compression: None,
             header: PacketHeader::ReliableSequenced {
                 stream_id,
                 seq_id: 0,
@@ -96,6 +117,7 @@ impl Packet {
     pub fn reliable_sequenced_static<B: IntoStaticBytes>(bytes: B, stream_id: Option<u8>) -> Self {
         Self {
             bytes: bytes.into_bytes(),
+            compression: None,
             header: PacketHeader::ReliableSequenced {
                 stream_id,
                 seq_id: 0,
@@ -109,6 +131,7 @@ impl Packet {
     pub fn reliable_unordered<B: IntoBytes>(bytes: B) -> Self {
         Self {
             bytes: bytes.into_bytes(),
+            compression: None,
             header: PacketHeader::ReliableUnordered,
         }
     }
@@ -119,6 +142,7 @@ impl Packet {
     pub fn reliable_unordered_static<B: IntoStaticBytes>(bytes: B) -> Self {
         Self {
             bytes: bytes.into_bytes(),
+            compression: None,
             header: PacketHeader::ReliableUnordered,
         }
     }
@@ -129,6 +153,7 @@ impl Packet {
     pub fn unreliable_sequenced<B: IntoBytes>(bytes: B, stream_id: Option<u8>) -> Self {
         Self {
             bytes: bytes.into_bytes(),
+            compression: None,
             header: PacketHeader::UnreliableSequenced {
                 stream_id,
                 seq_id: 0,
@@ -145,6 +170,7 @@ impl Packet {
     ) -> Self {
         Self {
             bytes: bytes.into_bytes(),
+            compression: None,
             header: PacketHeader::UnreliableSequenced {
                 stream_id,
                 seq_id: 0,
@@ -158,6 +184,7 @@ impl Packet {
     pub fn unreliable<B: IntoBytes>(bytes: B) -> Self {
         Self {
             bytes: bytes.into_bytes(),
+            compression: None,
             header: PacketHeader::Unreliable,
         }
     }
@@ -168,6 +195,7 @@ impl Packet {
     pub fn unreliable_static<B: IntoStaticBytes>(bytes: B) -> Self {
         Self {
             bytes: bytes.into_bytes(),
+            compression: None,
             header: PacketHeader::Unreliable,
         }
     }
@@ -207,7 +235,7 @@ impl IntoBytes for String {
     }
 }
 
-impl<'a> IntoBytes for &'a [u8] {
+impl IntoBytes for &[u8] {
     fn into_bytes(self) -> Bytes {
         // TODO: specialization for
         // 'static when it is stable
@@ -215,7 +243,7 @@ impl<'a> IntoBytes for &'a [u8] {
     }
 }
 
-impl<'a, const C: usize> IntoBytes for &'a [u8; C] {
+impl<const C: usize> IntoBytes for &[u8; C] {
     fn into_bytes(self) -> Bytes {
         // TODO: specialization for
         // 'static when it is stable
@@ -231,7 +259,7 @@ impl<const C: usize> IntoBytes for [u8; C] {
     }
 }
 
-impl<'a> IntoBytes for &'a str {
+impl IntoBytes for &str {
     fn into_bytes(self) -> Bytes {
         // TODO: specialization for
         // 'static when it is stable
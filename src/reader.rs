@@ -1,10 +1,13 @@
 use crate::{
+    inner::Closed,
     packet::{Packet, PacketHeader},
+    socket::DisconnectReason,
     unwrap_or,
+    wire::{self, WireError},
 };
 use bytes::{Bytes, BytesMut};
 use futures::{stream::SelectAll, StreamExt};
-use quinn::{ConnectionError, Datagrams, IncomingUniStreams, RecvStream};
+use quinn::{ConnectionError, Datagrams, IncomingUniStreams, ReadError, RecvStream};
 use std::{collections::HashMap, io::Error};
 use tokio::sync::{broadcast, mpsc};
 use tokio_util::codec::{FramedRead, LengthDelimitedCodec};
@@ -16,6 +19,7 @@ pub async fn reader_worker_job(
     mut datagrams: Datagrams,
     mut send: mpsc::Sender<Packet>,
     mut should_stop: broadcast::Receiver<()>,
+    closed: Closed,
 ) {
     let mut recv_streams = SelectAll::new();
 
@@ -35,15 +39,45 @@ pub async fn reader_worker_job(
         let datagram_stream = datagrams.next();
 
         if tokio::select! {
-            stream = new_stream => handle_new_stream(stream, &mut recv_streams),
-            Some(bytes) = old_stream => handle_old_stream(bytes, &mut send, &mut reliable_seq, &mut unreliable_seq).await,
-            bytes = datagram_stream => handle_datagram(bytes, &mut send, &mut reliable_seq, &mut unreliable_seq).await,
+            stream = new_stream => handle_new_stream(stream, &mut recv_streams, &closed),
+            Some(bytes) = old_stream => handle_old_stream(bytes, &mut send, &mut reliable_seq, &mut unreliable_seq, &closed).await,
+            bytes = datagram_stream => handle_datagram(bytes, &mut send, &mut reliable_seq, &mut unreliable_seq, &closed).await,
             _ = should_stop.recv() => true,
         } {
             break;
         };
     }
 
+    // streams the peer finished before closing
+    // the connection can still be read to the end
+    if closed.reason().is_some() {
+        loop {
+            let bytes = tokio::select! {
+                bytes = recv_streams.next() => bytes,
+                _ = should_stop.recv() => break,
+            };
+
+            match bytes {
+                Some(Ok(bytes)) => {
+                    if handle_old_stream(
+                        Ok(bytes),
+                        &mut send,
+                        &mut reliable_seq,
+                        &mut unreliable_seq,
+                        &closed,
+                    )
+                    .await
+                    {
+                        break;
+                    }
+                }
+                // unfinished streams end with an error
+                Some(Err(_)) => {}
+                None => break,
+            }
+        }
+    }
+
     log::debug!("Reader worker stopped");
 }
 
@@ -51,6 +85,7 @@ pub async fn reader_worker_job(
 fn handle_new_stream(
     stream: Option<Result<FRead, ConnectionError>>,
     recv_streams: &mut SelectAll<FRead>,
+    closed: &Closed,
 ) -> bool {
     let stream = stream.ok_or("Empty new stream");
 
@@ -58,7 +93,7 @@ fn handle_new_stream(
         return true;
     });
 
-    let stream = unwrap_or!(stream, {
+    let stream = unwrap_or!(stream.map_err(|err| lost(err, closed)), {
         return true;
     });
 
@@ -72,8 +107,11 @@ async fn handle_old_stream(
     send: &mut mpsc::Sender<Packet>,
     reliable_seq: &mut HashMap<Option<u8>, u16>,
     unreliable_seq: &mut HashMap<Option<u8>, u16>,
+    closed: &Closed,
 ) -> bool {
-    let packet = bytes.map(|b| bincode::deserialize(&b[..]));
+    let packet = bytes
+        .map_err(|err| stream_lost(err, closed))
+        .map(|b| wire::decode(b.freeze()).map_err(|err| malformed(err, closed)));
 
     let packet = unwrap_or!(packet, {
         return true;
@@ -96,10 +134,12 @@ async fn handle_datagram(
     send: &mut mpsc::Sender<Packet>,
     reliable_seq: &mut HashMap<Option<u8>, u16>,
     unreliable_seq: &mut HashMap<Option<u8>, u16>,
+    closed: &Closed,
 ) -> bool {
-    let packet = bytes
-        .ok_or("Empty datagram")
-        .map(|b| b.map(|b| bincode::deserialize(&b[..])));
+    let packet = bytes.ok_or("Empty datagram").map(|b| {
+        b.map_err(|err| lost(err, closed))
+            .map(|b| wire::decode(b).map_err(|err| malformed(err, closed)))
+    });
 
     let packet = unwrap_or!(packet, {
         return true;
@@ -120,6 +160,24 @@ async fn handle_datagram(
     }
 }
 
+fn lost(err: ConnectionError, closed: &Closed) -> ConnectionError {
+    closed.lost(&err);
+    err
+}
+
+fn stream_lost(err: Error, closed: &Closed) -> Error {
+    if let Some(ReadError::ConnectionLost(lost)) = err.get_ref().and_then(|err| err.downcast_ref())
+    {
+        closed.lost(lost);
+    }
+    err
+}
+
+fn malformed(err: WireError, closed: &Closed) -> WireError {
+    closed.fail(DisconnectReason::MalformedPacket(err.to_string()));
+    err
+}
+
 fn drop_sequenced(
     packet: Packet,
     reliable_seq: &mut HashMap<Option<u8>, u16>,
@@ -188,22 +246,22 @@ mod tests {
         let mut seq = HashMap::new();
         seq.insert(None, 0);
 
-        assert!(drop_sequenced_common(None, 1, &mut seq) == true);
-        assert!(drop_sequenced_common(None, 1, &mut seq) == false);
-        assert!(drop_sequenced_common(None, 1, &mut seq) == false);
-        assert!(drop_sequenced_common(None, 2, &mut seq) == true);
-        assert!(drop_sequenced_common(None, 2, &mut seq) == false);
-        assert!(drop_sequenced_common(None, 2, &mut seq) == false);
-        assert!(drop_sequenced_common(None, 200, &mut seq) == true);
-        assert!(drop_sequenced_common(None, 2, &mut seq) == false);
-        assert!(drop_sequenced_common(None, u16::MAX / 4, &mut seq) == true);
-        assert!(drop_sequenced_common(None, u16::MAX / 2, &mut seq) == true);
-        assert!(drop_sequenced_common(None, u16::MAX / 4 * 3, &mut seq) == true);
-        assert!(drop_sequenced_common(None, u16::MAX - 100, &mut seq) == true);
-        assert!(drop_sequenced_common(None, u16::MAX - 100, &mut seq) == false);
-        assert!(drop_sequenced_common(None, u16::MAX - 99, &mut seq) == true);
-        assert!(drop_sequenced_common(None, u16::MAX - 99, &mut seq) == false);
-        assert!(drop_sequenced_common(None, 0, &mut seq) == true);
-        assert!(drop_sequenced_common(None, 0, &mut seq) == false);
+        assert!(drop_sequenced_common(None, 1, &mut seq));
+        assert!(!drop_sequenced_common(None, 1, &mut seq));
+        assert!(!drop_sequenced_common(None, 1, &mut seq));
+        assert!(drop_sequenced_common(None, 2, &mut seq));
+        assert!(!drop_sequenced_common(None, 2, &mut seq));
+        assert!(!drop_sequenced_common(None, 2, &mut seq));
+        assert!(drop_sequenced_common(None, 200, &mut seq));
+        assert!(!drop_sequenced_common(None, 2, &mut seq));
+        assert!(drop_sequenced_common(None, u16::MAX / 4, &mut seq));
+        assert!(drop_sequenced_common(None, u16::MAX / 2, &mut seq));
+        assert!(drop_sequenced_common(None, u16::MAX / 4 * 3, &mut seq));
+        assert!(drop_sequenced_common(None, u16::MAX - 100, &mut seq));
+        assert!(!drop_sequenced_common(None, u16::MAX - 100, &mut seq));
+        assert!(drop_sequenced_common(None, u16::MAX - 99, &mut seq));
+        assert!(!drop_sequenced_common(None, u16::MAX - 99, &mut seq));
+        assert!(drop_sequenced_common(None, 0, &mut seq));
+        assert!(!drop_sequenced_common(None, 0, &mut seq));
     }
 }
@@ -0,0 +1,872 @@
+use crate::{
+    config::SocketConfig,
+    filter::FilterConfig,
+    packet::Packet,
+    session::{
+        SessionConfig, SessionHandshake, SessionRegistry, SessionRequest, SessionState,
+        SessionToken,
+    },
+    socket::{unspecified, ConnectError, DisconnectReason, Socket, ToServerAddrs},
+};
+use quinn::ClientConfig;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::{collections::VecDeque, future::pending, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    select,
+    sync::{
+        broadcast,
+        mpsc::{
+            self,
+            error::{TryRecvError, TrySendError},
+        },
+    },
+    task::JoinHandle,
+    time::{interval, sleep},
+};
+
+//
+
+/// [`Socket`] that reconnects when the connection is lost
+///
+/// the channels stay the same across reconnects and
+/// packets sent while reconnecting are queued, packets
+/// that were in flight when the connection was lost
+/// are not sent again unless a session is used
+///
+/// on the server side it waits for
+/// the client to resume the session
+pub struct ReconnectingSocket {
+    channels: Option<(mpsc::Sender<Packet>, mpsc::Receiver<Packet>)>,
+    events: mpsc::UnboundedReceiver<ReconnectEvent>,
+    worker: Option<JoinHandle<()>>,
+    should_stop: broadcast::Sender<()>,
+}
+
+/// Exponential backoff between reconnect attempts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    /// delay before the first attempt
+    pub initial: Duration,
+
+    /// upper limit for the delay
+    pub max: Duration,
+
+    /// the delay is multiplied by
+    /// this after every failed attempt
+    pub multiplier: f64,
+
+    /// `0.0..=1.0`, each delay is randomly
+    /// shortened by up to this fraction
+    pub jitter: f64,
+
+    /// `None` retries forever
+    pub max_attempts: Option<u32>,
+}
+
+/// Returned by [`ReconnectingSocket::next_event`]
+#[derive(Debug)]
+pub enum ReconnectEvent {
+    /// the connection was lost
+    Disconnected(DisconnectReason),
+
+    /// waiting `delay` before reconnect
+    /// attempt `attempt`, starting from 1
+    Reconnecting { attempt: u32, delay: Duration },
+
+    /// the channels are connected to the new connection
+    Reconnected,
+
+    /// [`Backoff::max_attempts`] ran out, the peer
+    /// disconnected on purpose, the handshake failed
+    /// for good or the session could not be resumed,
+    /// the channels are closed
+    GaveUp(ConnectError),
+}
+
+//
+
+impl ReconnectingSocket {
+    /// see [`Socket::connect_config`]
+    ///
+    /// reconnects to the address
+    /// the first connection used
+    pub async fn connect_config<A: ToServerAddrs>(
+        addr: A,
+        config: ClientConfig,
+        backoff: Backoff,
+    ) -> Result<Self, ConnectError> {
+        let server_name = addr.server_name();
+        let socket = Socket::connect_config(addr, config.clone()).await?;
+
+        let connector = Connector {
+            addr: socket.remote(),
+            server_name,
+            config,
+            socket: SocketConfig::default(),
+            backoff,
+        };
+        Ok(Self::client(socket, connector, None))
+    }
+
+    /// Like [`ReconnectingSocket::connect_config`], with the
+    /// queue sizes, transport and handshake settings from `config`
+    ///
+    /// see [`Socket::connect_with`]
+    pub async fn connect_with<A: ToServerAddrs>(
+        addr: A,
+        mut client: ClientConfig,
+        config: SocketConfig,
+        backoff: Backoff,
+    ) -> Result<Self, ConnectError> {
+        let server_name = config
+            .server_name
+            .clone()
+            .unwrap_or_else(|| addr.server_name());
+        client.transport = Arc::new(config.transport_config());
+        let socket = Socket::connect_with(addr, client.clone(), config.clone()).await?;
+
+        let connector = Connector {
+            addr: socket.remote(),
+            server_name,
+            config: client,
+            socket: config,
+            backoff,
+        };
+        Ok(Self::client(socket, connector, None))
+    }
+
+    /// the first connection is made before returning
+    ///
+    /// see [`Socket::connect_with_filter`]
+    pub async fn connect_with_filter(
+        addr: SocketAddr,
+        server_name: &str,
+        config: ClientConfig,
+        filter: FilterConfig,
+        backoff: Backoff,
+    ) -> Result<Self, ConnectError> {
+        let socket =
+            Socket::connect_with_filter(addr, server_name, config.clone(), &filter).await?;
+
+        let connector = Connector {
+            addr,
+            server_name: server_name.to_owned(),
+            config,
+            socket: SocketConfig::default().with_filter(filter),
+            backoff,
+        };
+        Ok(Self::client(socket, connector, None))
+    }
+
+    /// Like [`ReconnectingSocket::connect_with_filter`], but
+    /// resumes the session after reconnecting, so that
+    /// reliable packets are not lost or duplicated
+    ///
+    /// the server has to accept sessions with
+    /// [`crate::listener::Listener::next_session`]
+    pub async fn connect_session(
+        addr: SocketAddr,
+        server_name: &str,
+        config: ClientConfig,
+        filter: FilterConfig,
+        backoff: Backoff,
+        session: SessionConfig,
+    ) -> Result<Self, ConnectError> {
+        let socket_config = SocketConfig::default().with_filter(filter);
+        let socket = Socket::connect_session(
+            addr,
+            server_name,
+            config.clone(),
+            &socket_config,
+            unspecified(addr),
+            SessionHandshake::Request(SessionRequest::New),
+        )
+        .await?;
+
+        let token = match socket.session {
+            Some(grant) => grant.token,
+            None => {
+                socket.close().await;
+                return Err(ConnectError::SessionRejected);
+            }
+        };
+
+        let connector = Connector {
+            addr,
+            server_name: server_name.to_owned(),
+            config,
+            socket: socket_config,
+            backoff,
+        };
+        Ok(Self::client(
+            socket,
+            connector,
+            Some(Session::new(token, &session)),
+        ))
+    }
+
+    fn client(socket: Socket, connector: Connector, session: Option<Session>) -> Self {
+        let queues = connector.socket.clone();
+        Self::spawn(
+            socket,
+            Reconnect::Client(Box::new(connector)),
+            session,
+            &queues,
+        )
+    }
+
+    /// the queue sizes are taken from `config`
+    pub(crate) fn spawn(
+        socket: Socket,
+        reconnect: Reconnect,
+        session: Option<Session>,
+        config: &SocketConfig,
+    ) -> Self {
+        let (worker_send, recv) = mpsc::channel(config.recv_queue.max(1));
+        let (send, worker_recv) = mpsc::channel(config.send_queue.max(1));
+        let (event_send, events) = mpsc::unbounded_channel();
+        let (should_stop, worker_should_stop) = broadcast::channel(1);
+
+        let worker = tokio::spawn(reconnect_worker_job(
+            socket,
+            reconnect,
+            session,
+            worker_recv,
+            worker_send,
+            event_send,
+            worker_should_stop,
+        ));
+
+        Self {
+            channels: Some((send, recv)),
+            events,
+            worker: Some(worker),
+            should_stop,
+        }
+    }
+
+    /// Reconnect events, `None` after
+    /// the socket stopped reconnecting
+    pub async fn next_event(&mut self) -> Option<ReconnectEvent> {
+        self.events.recv().await
+    }
+
+    pub fn try_next_event(&mut self) -> Option<ReconnectEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// panics if socket is split
+    pub async fn recv(&mut self) -> Option<Packet> {
+        self.receiver().recv().await
+    }
+
+    /// panics if socket is split
+    pub fn try_recv(&mut self) -> Result<Packet, TryRecvError> {
+        self.receiver().try_recv()
+    }
+
+    /// panics if socket is split
+    pub async fn send(&self, packet: Packet) -> Option<()> {
+        self.sender().send(packet).await.ok()
+    }
+
+    /// panics if socket is split
+    pub fn try_send(&self, packet: Packet) -> Result<(), TrySendError<Packet>> {
+        self.sender().try_send(packet)
+    }
+
+    /// returns the sender and receiver parts
+    ///
+    /// they keep working across reconnects
+    /// as long as this socket is kept
+    ///
+    /// panics if split twice
+    pub fn split(&mut self) -> (mpsc::Sender<Packet>, mpsc::Receiver<Packet>) {
+        self.channels.take().expect("channels already taken")
+    }
+
+    /// _unsplit_
+    pub fn unite(&mut self, channels: (mpsc::Sender<Packet>, mpsc::Receiver<Packet>)) {
+        self.channels = Some(channels);
+    }
+
+    /// panics if socket is split
+    pub fn channels(&self) -> &(mpsc::Sender<Packet>, mpsc::Receiver<Packet>) {
+        self.channels.as_ref().expect("channels already taken")
+    }
+
+    /// panics if socket is split
+    pub fn channels_mut(&mut self) -> &mut (mpsc::Sender<Packet>, mpsc::Receiver<Packet>) {
+        self.channels.as_mut().expect("channels already taken")
+    }
+
+    /// panics if socket is split
+    pub fn sender(&self) -> &mpsc::Sender<Packet> {
+        &self.channels().0
+    }
+
+    /// panics if socket is split
+    pub fn receiver(&mut self) -> &mut mpsc::Receiver<Packet> {
+        &mut self.channels_mut().1
+    }
+
+    /// Stops reconnecting and closes the current connection
+    pub async fn close(mut self) {
+        let _ = self.should_stop.send(());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.await;
+        }
+    }
+}
+
+impl Backoff {
+    pub fn with_initial(mut self, initial: Duration) -> Self {
+        self.initial = initial;
+        self
+    }
+
+    pub fn with_max(mut self, max: Duration) -> Self {
+        self.max = max;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// delay before `attempt`, starting
+    /// from 1, without the jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self.initial.as_secs_f64() * self.multiplier.powi(exp);
+        Duration::try_from_secs_f64(delay)
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+
+    fn jittered_delay(&self, attempt: u32) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0) * random();
+        self.delay(attempt).mul_f64(1.0 - jitter)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(250),
+            max: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Drop for ReconnectingSocket {
+    fn drop(&mut self) {
+        // the worker closes the connection
+        let _ = self.should_stop.send(());
+    }
+}
+
+//
+
+pub(crate) struct Connector {
+    addr: SocketAddr,
+    server_name: String,
+    config: ClientConfig,
+    socket: SocketConfig,
+    backoff: Backoff,
+}
+
+// how a lost connection is replaced
+pub(crate) enum Reconnect {
+    // the client connects again
+    Client(Box<Connector>),
+
+    // the server waits for the client to resume the session
+    Server {
+        token: SessionToken,
+        resumed: mpsc::UnboundedReceiver<Socket>,
+        sessions: Arc<SessionRegistry>,
+        timeout: Duration,
+    },
+
+    // the client did not want a session
+    Never,
+}
+
+// why `forward` returned
+enum Forwarded {
+    // the connection was lost
+    Lost(DisconnectReason),
+
+    // the client resumed the session on a new connection
+    Resumed(Socket),
+
+    // the user dropped the channels
+    Stopped,
+}
+
+pub(crate) struct Session {
+    token: SessionToken,
+    state: SessionState,
+    ack_interval: Duration,
+}
+
+impl Connector {
+    async fn reconnect(
+        &self,
+        events: &mpsc::UnboundedSender<ReconnectEvent>,
+        session: Option<SessionToken>,
+    ) -> Result<Socket, ConnectError> {
+        let handshake = match session {
+            Some(token) => SessionHandshake::Request(SessionRequest::Resume(token)),
+            None => SessionHandshake::Disabled,
+        };
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+
+            let delay = self.backoff.jittered_delay(attempt);
+            let _ = events.send(ReconnectEvent::Reconnecting { attempt, delay });
+            sleep(delay).await;
+
+            match Socket::connect_session(
+                self.addr,
+                &self.server_name,
+                self.config.clone(),
+                &self.socket,
+                self.socket.local.unwrap_or_else(|| unspecified(self.addr)),
+                handshake,
+            )
+            .await
+            {
+                // the packets of the old session can't be delivered
+                Ok(socket)
+                    if session.is_some()
+                        && !matches!(socket.session, Some(grant) if grant.resumed) =>
+                {
+                    socket.close().await;
+                    return Err(ConnectError::SessionRejected);
+                }
+                Ok(socket) => return Ok(socket),
+                // a rejected client or certificate stays rejected
+                Err(err) if !err.is_transient() => return Err(err),
+                Err(err) if matches!(self.backoff.max_attempts, Some(max) if attempt >= max) => {
+                    return Err(err)
+                }
+                Err(err) => log::debug!("Reconnect attempt {attempt} failed: {err}"),
+            }
+        }
+    }
+}
+
+impl Reconnect {
+    async fn reconnect(
+        &mut self,
+        events: &mpsc::UnboundedSender<ReconnectEvent>,
+        session: Option<SessionToken>,
+    ) -> Result<Socket, ConnectError> {
+        match self {
+            Reconnect::Client(connector) => connector.reconnect(events, session).await,
+            Reconnect::Server {
+                resumed, timeout, ..
+            } => match tokio::time::timeout(*timeout, resumed.recv()).await {
+                Ok(Some(socket)) => Ok(socket),
+                _ => Err(ConnectError::SessionExpired),
+            },
+            Reconnect::Never => Err(ConnectError::SessionRejected),
+        }
+    }
+
+    // a connection that resumed the session, the client
+    // might notice a lost connection before the server does
+    async fn resumed(&mut self) -> Socket {
+        match self {
+            Reconnect::Server { resumed, .. } => match resumed.recv().await {
+                Some(socket) => socket,
+                None => pending().await,
+            },
+            _ => pending().await,
+        }
+    }
+}
+
+impl Drop for Reconnect {
+    fn drop(&mut self) {
+        if let Reconnect::Server {
+            token, sessions, ..
+        } = self
+        {
+            sessions.unregister(*token);
+        }
+    }
+}
+
+impl Session {
+    pub(crate) fn new(token: SessionToken, config: &SessionConfig) -> Self {
+        Self {
+            token,
+            state: SessionState::new(config),
+            ack_interval: config.ack_interval,
+        }
+    }
+}
+
+async fn reconnect_worker_job(
+    mut socket: Socket,
+    mut reconnect: Reconnect,
+    mut session: Option<Session>,
+    mut outgoing: mpsc::Receiver<Packet>,
+    incoming: mpsc::Sender<Packet>,
+    events: mpsc::UnboundedSender<ReconnectEvent>,
+    mut should_stop: broadcast::Receiver<()>,
+) {
+    loop {
+        let forwarded = select! {
+            forwarded = forward(
+                &mut socket,
+                &mut reconnect,
+                &mut outgoing,
+                &incoming,
+                &mut session,
+            ) => forwarded,
+            _ = should_stop.recv() => Forwarded::Stopped,
+        };
+
+        let reason = match forwarded {
+            Forwarded::Lost(reason) => reason,
+            // the old connection is replaced before it times out
+            Forwarded::Resumed(resumed) => {
+                std::mem::replace(&mut socket, resumed).close().await;
+                let _ = events.send(ReconnectEvent::Reconnected);
+                continue;
+            }
+            // the user is gone
+            Forwarded::Stopped => {
+                socket.close().await;
+                break;
+            }
+        };
+
+        let _ = events.send(ReconnectEvent::Disconnected(reason.clone()));
+        socket.close().await;
+
+        if let Reconnect::Never = reconnect {
+            break;
+        }
+
+        // the peer does not want this connection
+        if let DisconnectReason::Disconnected { code, reason } = reason {
+            let _ = events.send(ReconnectEvent::GaveUp(ConnectError::Disconnected {
+                code,
+                reason,
+            }));
+            break;
+        }
+
+        let token = session.as_ref().map(|session| session.token);
+        let result = select! {
+            result = reconnect.reconnect(&events, token) => result,
+            _ = should_stop.recv() => break,
+        };
+
+        socket = match result {
+            Ok(socket) => socket,
+            Err(err) => {
+                let _ = events.send(ReconnectEvent::GaveUp(err));
+                break;
+            }
+        };
+
+        let _ = events.send(ReconnectEvent::Reconnected);
+    }
+
+    log::debug!("Reconnect worker stopped");
+}
+
+async fn forward(
+    socket: &mut Socket,
+    reconnect: &mut Reconnect,
+    outgoing: &mut mpsc::Receiver<Packet>,
+    incoming: &mpsc::Sender<Packet>,
+    session: &mut Option<Session>,
+) -> Forwarded {
+    let (send, mut recv) = socket.split();
+
+    // packets the peer might have missed
+    // go first, duplicates are dropped
+    let mut pending: VecDeque<Packet> = session
+        .iter_mut()
+        .flat_map(|session| session.state.replay())
+        .collect();
+    let mut closing = false;
+
+    // one half can be dropped
+    // while the other is in use
+    let mut sending = true;
+    let mut receiving = true;
+
+    let ack_interval = session
+        .as_ref()
+        .map_or(Duration::MAX, |session| session.ack_interval);
+    let mut ack = interval(ack_interval.max(Duration::from_millis(1)));
+
+    let forwarded = loop {
+        let full = matches!(session, Some(session) if session.state.is_full());
+
+        select! {
+            permit = send.reserve(), if !pending.is_empty() && !closing => match permit {
+                Ok(permit) => permit.send(pending.pop_front().unwrap()),
+                // the connection is closing,
+                // `recv` notices that next
+                Err(_) => closing = true,
+            },
+            packet = outgoing.recv(), if sending && pending.is_empty() && !full => match packet {
+                Some(packet) => pending.push_back(match session {
+                    Some(session) => session.state.send(packet),
+                    None => packet,
+                }),
+                None if receiving => sending = false,
+                None => break Forwarded::Stopped,
+            },
+            packet = recv.recv() => match packet {
+                Some(packet) => {
+                    let packet = match session {
+                        Some(session) => session.state.recv(packet),
+                        None => Some(packet),
+                    };
+                    if let Some(packet) = packet.filter(|_| receiving) {
+                        receiving = incoming.send(packet).await.is_ok();
+                    }
+                    if !sending && !receiving {
+                        break Forwarded::Stopped;
+                    }
+                }
+                None => break Forwarded::Lost(
+                    socket
+                        .disconnect_reason()
+                        .unwrap_or(DisconnectReason::ConnectionLost(
+                            quinn::ConnectionError::LocallyClosed,
+                        )),
+                ),
+            },
+            _ = ack.tick(), if session.is_some() => {
+                pending.extend(session.as_mut().and_then(|session| session.state.ack()));
+            }
+            resumed = reconnect.resumed() => break Forwarded::Resumed(resumed),
+        }
+    };
+
+    socket.unite((send, recv));
+    forwarded
+}
+
+// uniform in `0.0..1.0`
+fn random() -> f64 {
+    let mut bytes = [0; 8];
+    // no jitter without randomness
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return 0.0;
+    }
+    (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::SocketConfig,
+        filter::FilterConfig,
+        listener::Listener,
+        packet::Packet,
+        reconnect::{Backoff, Reconnect, ReconnectEvent, ReconnectingSocket, Session},
+        session::{SessionConfig, SessionHandshake, SessionRequest},
+        socket::{unspecified, ConnectError, DisconnectReason, Socket},
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::time::{sleep, timeout};
+
+    #[test]
+    fn backoff_test() {
+        let backoff = Backoff::default()
+            .with_initial(Duration::from_millis(100))
+            .with_max(Duration::from_secs(1))
+            .with_jitter(0.5);
+
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(200));
+        assert_eq!(backoff.delay(4), Duration::from_millis(800));
+        assert_eq!(backoff.delay(5), Duration::from_secs(1));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+
+        for attempt in 1..10 {
+            let delay = backoff.jittered_delay(attempt);
+            assert!(delay <= backoff.delay(attempt));
+            assert!(delay >= backoff.delay(attempt) / 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn gave_up_test() {
+        let backoff = Backoff::default().with_initial(Duration::from_millis(10));
+
+        // kicked right after connecting
+        let mut listener = Listener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addrs()[0];
+        tokio::spawn(async move {
+            while let Ok(socket) = listener.next().await {
+                socket.disconnect(10, "kicked").await;
+            }
+        });
+        let mut client = ReconnectingSocket::connect_with(
+            addr,
+            Socket::default_config(),
+            SocketConfig::default(),
+            backoff,
+        )
+        .await
+        .unwrap();
+        timeout(Duration::from_secs(5), async {
+            assert!(matches!(
+                client.next_event().await,
+                Some(ReconnectEvent::Disconnected(
+                    DisconnectReason::Disconnected { code: 10, .. }
+                ))
+            ));
+            assert!(matches!(
+                client.next_event().await,
+                Some(ReconnectEvent::GaveUp(ConnectError::Disconnected {
+                    code: 10,
+                    ..
+                }))
+            ));
+            assert!(client.next_event().await.is_none());
+        })
+        .await
+        .unwrap();
+
+        // only the first handshake passes
+        let mut listener = Listener::bind("127.0.0.1:0").unwrap();
+        let passed = Arc::new(AtomicBool::new(false));
+        listener.set_filter(FilterConfig::default().with_hook(move |_| {
+            let passed = passed.swap(true, Ordering::SeqCst);
+            async move {
+                match passed {
+                    true => Err("banned".to_owned()),
+                    false => Ok(()),
+                }
+            }
+        }));
+        let addr = listener.local_addrs()[0];
+        tokio::spawn(async move { while listener.next().await.is_ok() {} });
+        let mut client = ReconnectingSocket::connect_with(
+            addr,
+            Socket::default_config(),
+            SocketConfig::default(),
+            backoff,
+        )
+        .await
+        .unwrap();
+        timeout(Duration::from_secs(5), async {
+            assert!(matches!(
+                client.next_event().await,
+                Some(ReconnectEvent::Disconnected(
+                    DisconnectReason::ConnectionLost(_)
+                ))
+            ));
+            assert!(matches!(
+                client.next_event().await,
+                Some(ReconnectEvent::Reconnecting { attempt: 1, .. })
+            ));
+            assert!(matches!(
+                client.next_event().await,
+                Some(ReconnectEvent::GaveUp(ConnectError::Rejected(reason))) if reason == "banned"
+            ));
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn session_resume_test() {
+        let mut listener = Listener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addrs()[0];
+        let config = SocketConfig::default();
+        let connect = |request| {
+            Socket::connect_session(
+                addr,
+                "localhost",
+                Socket::default_config(),
+                &config,
+                unspecified(addr),
+                SessionHandshake::Request(request),
+            )
+        };
+
+        let (server, old) = tokio::join!(listener.next_session(), connect(SessionRequest::New));
+        let (mut server, mut old) = (server.unwrap(), old.unwrap());
+        let token = old.session.unwrap().token;
+        tokio::spawn(async move { while listener.next_session().await.is_ok() {} });
+
+        // the old connection never acknowledges anything
+        for i in 0..10u8 {
+            server.send(Packet::ordered(vec![i], None)).await.unwrap();
+        }
+        sleep(Duration::from_millis(100)).await;
+
+        // the old connection is still open, the server
+        // switches over without waiting for it to time out
+        let resumed = connect(SessionRequest::Resume(token)).await.unwrap();
+        assert!(matches!(resumed.session, Some(grant) if grant.resumed));
+        let mut client = ReconnectingSocket::spawn(
+            resumed,
+            Reconnect::Never,
+            Some(Session::new(token, &SessionConfig::default())),
+            &config,
+        );
+
+        timeout(Duration::from_secs(5), async {
+            assert!(matches!(
+                server.next_event().await,
+                Some(ReconnectEvent::Reconnected)
+            ));
+            while old.recv().await.is_some() {}
+
+            for i in 10..20u8 {
+                server.send(Packet::ordered(vec![i], None)).await.unwrap();
+            }
+            for i in 0..20u8 {
+                assert_eq!(client.recv().await.unwrap().bytes[..], [i]);
+            }
+
+            client.send(Packet::ordered("back", None)).await.unwrap();
+            assert_eq!(server.recv().await.unwrap().bytes, "back");
+        })
+        .await
+        .unwrap();
+
+        sleep(Duration::from_millis(100)).await;
+        assert!(client.try_recv().is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use crate::{
+    packet::{Packet, PacketHeader},
+    socket::Socket,
+};
+use bytes::{Buf, BufMut, BytesMut};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+//
+
+/// Opt-in session layer settings
+///
+/// a session survives reconnects, the reliable
+/// packets that the peer did not acknowledge are
+/// sent again and duplicates are dropped
+///
+/// only [`PacketHeader::Ordered`] and
+/// [`PacketHeader::ReliableUnordered`] packets are
+/// sent again, sequenced packets only carry the
+/// latest state and unreliable packets may be lost
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionConfig {
+    /// how many unacknowledged reliable packets are
+    /// kept, sending waits while the buffer is full
+    pub replay_buffer: usize,
+
+    /// how often received packets are acknowledged
+    pub ack_interval: Duration,
+
+    /// how long the server waits for
+    /// the client to resume the session
+    pub resume_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct SessionToken([u8; 16]);
+
+/// sent by the client after the filter packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum SessionRequest {
+    New,
+    Resume(SessionToken),
+}
+
+/// sent by the server with the filter verdict
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SessionGrant {
+    pub(crate) token: SessionToken,
+    pub(crate) resumed: bool,
+}
+
+/// session part of the filter handshake
+#[derive(Clone, Copy)]
+pub(crate) enum SessionHandshake<'a> {
+    Disabled,
+    Request(SessionRequest),
+    Grant(&'a SessionRegistry),
+}
+
+/// sessions of a listener that can be resumed
+#[derive(Debug, Default)]
+pub(crate) struct SessionRegistry {
+    sessions: Mutex<HashMap<SessionToken, mpsc::UnboundedSender<Socket>>>,
+}
+
+/// replay buffer and duplicate
+/// filter of one session
+#[derive(Debug)]
+pub(crate) struct SessionState {
+    replay_buffer: usize,
+
+    next_seq: u64,
+    unacked: VecDeque<(u64, Packet)>,
+
+    recv_next: u64,
+    recv_above: BTreeSet<u64>,
+    ack_pending: bool,
+}
+
+//
+
+impl SessionConfig {
+    pub fn with_replay_buffer(mut self, replay_buffer: usize) -> Self {
+        self.replay_buffer = replay_buffer;
+        self
+    }
+
+    pub fn with_ack_interval(mut self, ack_interval: Duration) -> Self {
+        self.ack_interval = ack_interval;
+        self
+    }
+
+    pub fn with_resume_timeout(mut self, resume_timeout: Duration) -> Self {
+        self.resume_timeout = resume_timeout;
+        self
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            replay_buffer: 1024,
+            ack_interval: Duration::from_millis(50),
+            resume_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl SessionToken {
+    fn random() -> Self {
+        let mut token = [0; 16];
+        SystemRandom::new()
+            .fill(&mut token)
+            .expect("no system randomness for the session token");
+        Self(token)
+    }
+}
+
+impl SessionRegistry {
+    /// starts a new session or resumes a known one,
+    /// `None` if the session to resume has ended
+    ///
+    /// a resumed session keeps taking connections until
+    /// it ends, so the handover in [`Self::resume`]
+    /// can't be refused after the grant was sent
+    pub(crate) fn grant(&self, request: SessionRequest) -> Option<SessionGrant> {
+        match request {
+            SessionRequest::New => Some(SessionGrant {
+                token: SessionToken::random(),
+                resumed: false,
+            }),
+            SessionRequest::Resume(token) => {
+                if !self.sessions.lock().unwrap().contains_key(&token) {
+                    return None;
+                }
+                Some(SessionGrant {
+                    token,
+                    resumed: true,
+                })
+            }
+        }
+    }
+
+    /// resumed connections of the session are sent to
+    /// the receiver, the latest one replaces the others
+    pub(crate) fn register(&self, token: SessionToken) -> mpsc::UnboundedReceiver<Socket> {
+        let (send, recv) = mpsc::unbounded_channel();
+        self.sessions.lock().unwrap().insert(token, send);
+        recv
+    }
+
+    pub(crate) fn unregister(&self, token: SessionToken) {
+        self.sessions.lock().unwrap().remove(&token);
+    }
+
+    /// hands the connection to the session,
+    /// false if the session already ended
+    pub(crate) fn resume(&self, token: SessionToken, socket: Socket) -> bool {
+        let sessions = self.sessions.lock().unwrap();
+        match sessions.get(&token) {
+            Some(session) => session.send(socket).is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl SessionState {
+    pub(crate) fn new(config: &SessionConfig) -> Self {
+        Self {
+            replay_buffer: config.replay_buffer.max(1),
+
+            next_seq: 0,
+            unacked: Default::default(),
+
+            recv_next: 0,
+            recv_above: Default::default(),
+            ack_pending: false,
+        }
+    }
+
+    /// the peer has not acknowledged enough packets
+    pub(crate) fn is_full(&self) -> bool {
+        self.unacked.len() >= self.replay_buffer
+    }
+
+    /// frames `packet`, reliable packets
+    /// are kept until they are acknowledged
+    pub(crate) fn send(&mut self, packet: Packet) -> Packet {
+        if !matches!(
+            packet.header,
+            PacketHeader::Ordered { .. } | PacketHeader::ReliableUnordered
+        ) {
+            return frame(packet, UNSEQUENCED, None);
+        }
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let packet = frame(packet, SEQUENCED, Some(seq));
+        self.unacked.push_back((seq, packet.clone()));
+        packet
+    }
+
+    /// unacknowledged packets, oldest first
+    ///
+    /// the last ack might have been lost with
+    /// the old connection, so it is sent again
+    pub(crate) fn replay(&mut self) -> impl Iterator<Item = Packet> + '_ {
+        self.ack_pending = true;
+        self.unacked.iter().map(|(_, packet)| packet.clone())
+    }
+
+    /// unframes `packet`, `None` for acks
+    /// and packets that were already received
+    pub(crate) fn recv(&mut self, mut packet: Packet) -> Option<Packet> {
+        let bytes = &mut packet.bytes;
+        if bytes.is_empty() {
+            log::debug!("Empty session frame");
+            return None;
+        }
+
+        match bytes.get_u8() {
+            UNSEQUENCED => Some(packet),
+            SEQUENCED if bytes.len() >= 8 => {
+                let seq = bytes.get_u64_le();
+                self.ack_pending = true;
+
+                if seq < self.recv_next || !self.recv_above.insert(seq) {
+                    return None;
+                }
+                while self.recv_above.remove(&self.recv_next) {
+                    self.recv_next += 1;
+                }
+
+                Some(packet)
+            }
+            ACK if bytes.len() >= 8 => {
+                let recv_next = bytes.get_u64_le();
+                while matches!(self.unacked.front(), Some((seq, _)) if *seq < recv_next) {
+                    self.unacked.pop_front();
+                }
+                None
+            }
+            tag => {
+                log::debug!("Invalid session frame {tag}");
+                None
+            }
+        }
+    }
+
+    /// acknowledges everything received since the last ack
+    pub(crate) fn ack(&mut self) -> Option<Packet> {
+        if !std::mem::take(&mut self.ack_pending) {
+            return None;
+        }
+
+        let mut bytes = BytesMut::with_capacity(9);
+        bytes.put_u8(ACK);
+        bytes.put_u64_le(self.recv_next);
+        // one ordered stream, not a new stream for every ack
+        Some(Packet::ordered(bytes.freeze(), None))
+    }
+}
+
+//
+
+fn frame(packet: Packet, tag: u8, seq: Option<u64>) -> Packet {
+    let mut bytes = BytesMut::with_capacity(9 + packet.bytes.len());
+    bytes.put_u8(tag);
+    if let Some(seq) = seq {
+        bytes.put_u64_le(seq);
+    }
+    bytes.put_slice(&packet.bytes);
+
+    Packet {
+        header: packet.header,
+        bytes: bytes.freeze(),
+        compression: packet.compression,
+    }
+}
+
+// session frame tags
+const UNSEQUENCED: u8 = 0;
+const SEQUENCED: u8 = 1;
+const ACK: u8 = 2;
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        packet::Packet,
+        session::{SessionConfig, SessionState},
+    };
+
+    #[test]
+    fn session_replay_test() {
+        let config = SessionConfig::default().with_replay_buffer(3);
+        let mut a = SessionState::new(&config);
+        let mut b = SessionState::new(&config);
+
+        let sent: Vec<Packet> = (0..3u8)
+            .map(|i| a.send(Packet::ordered(vec![i], None)))
+            .collect();
+        let unreliable = a.send(Packet::unreliable(vec![9]));
+        assert!(a.is_full());
+
+        // the second packet is lost with the connection
+        assert_eq!(b.recv(sent[0].clone()).unwrap().bytes[..], [0]);
+        assert_eq!(b.recv(sent[2].clone()).unwrap().bytes[..], [2]);
+        assert_eq!(b.recv(unreliable).unwrap().bytes[..], [9]);
+
+        // only the missing packet gets through again
+        let replayed: Vec<Packet> = a.replay().filter_map(|p| b.recv(p)).collect();
+        assert_eq!(replayed.len(), 1);
+        assert_eq!(replayed[0].bytes[..], [1]);
+
+        assert!(a.recv(b.ack().unwrap()).is_none());
+        assert!(!a.is_full());
+        assert_eq!(a.replay().count(), 0);
+        assert!(b.ack().is_none());
+    }
+}
@@ -1,18 +1,34 @@
-use crate::{attempt_all_async, filter::FilterError, inner::SocketInner, packet::Packet};
-use quinn::{ClientConfig, Endpoint, NewConnection};
+use crate::{
+    attempt_staggered,
+    cert::{self, CaVerifier, Fingerprint, PinnedVerifier},
+    compression::CompressionStats,
+    config::{FlowControl, SocketConfig},
+    filter::{FilterConfig, FilterError, Side},
+    inner::SocketInner,
+    interleave,
+    packet::Packet,
+    session::SessionHandshake,
+    version::Version,
+    ATTEMPT_DELAY,
+};
+use bytes::Bytes;
+use quinn::{ClientConfig, Endpoint, NewConnection, VarInt};
 use quinn_proto::ConnectionStats;
-use rustls::{client::ServerCertVerifier, Certificate};
+use rustls::{client::ServerCertVerifier, Certificate, PrivateKey, RootCertStore};
 use std::{
     io,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs},
     ops::{Deref, DerefMut},
     sync::Arc,
     time::Duration,
 };
 use thiserror::Error;
-use tokio::sync::mpsc::{
-    self,
-    error::{TryRecvError, TrySendError},
+use tokio::{
+    net::lookup_host,
+    sync::mpsc::{
+        self,
+        error::{TryRecvError, TrySendError},
+    },
 };
 
 //
@@ -41,42 +57,226 @@ pub enum ConnectError {
 
     #[error("peer filtered out ({0})")]
     FilterError(#[from] FilterError),
+
+    #[error("rejected by the server ({0})")]
+    Rejected(String),
+
+    #[error("server certificate rejected ({0})")]
+    CertificateRejected(String),
+
+    #[error("server certificate is not valid for the server name ({0})")]
+    ServerNameMismatch(String),
+
+    #[error("the server did not accept or resume the session")]
+    SessionRejected,
+
+    #[error("the client did not resume the session in time")]
+    SessionExpired,
+
+    #[error("disconnected by the peer ({code}: {reason})")]
+    Disconnected { code: u32, reason: String },
+}
+
+/// Why the connection to the peer was closed
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DisconnectReason {
+    /// the peer called [`Socket::disconnect`]
+    #[error("disconnected by the peer ({code}: {reason})")]
+    Disconnected { code: u32, reason: String },
+
+    /// the peer stopped responding
+    #[error("timed out")]
+    TimedOut,
+
+    /// the peer sent something that is not a packet
+    #[error("malformed packet ({0})")]
+    MalformedPacket(String),
+
+    #[error("failed to send ({0})")]
+    WriteFailed(String),
+
+    #[error("connection lost ({0})")]
+    ConnectionLost(quinn::ConnectionError),
+}
+
+/// Returned by [`Socket::next_event`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SocketEvent {
+    /// always the first event
+    Connected,
+
+    Packet(Packet),
+
+    /// the socket won't receive anything after this
+    Disconnected(DisconnectReason),
+
+    /// like [`SocketEvent::Disconnected`], but
+    /// the peer stopped responding
+    TimedOut,
+}
+
+/// [`ToSocketAddrs`] that also knows
+/// the server name used with SNI and
+/// certificate verification
+pub trait ToServerAddrs: ToSocketAddrs {
+    fn server_name(&self) -> String;
+
+    /// host and port to resolve without blocking,
+    /// `None` if [`ToSocketAddrs`] doesn't do lookups
+    fn host_port(&self) -> Option<(String, u16)> {
+        None
+    }
 }
 
 //
 
 impl Socket {
-    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, ConnectError> {
-        let config = Self::default_config();
-        let addrs = addr
-            .to_socket_addrs()
+    /// the server name is taken from the host part of `addr`
+    ///
+    /// the addresses are tried in parallel, IPv6 and IPv4
+    /// alternating and each attempt starting a bit after
+    /// the previous one (RFC 8305 Happy Eyeballs), the first
+    /// one to pass the filter handshake is used
+    pub async fn connect<A: ToServerAddrs>(addr: A) -> Result<Self, ConnectError> {
+        Self::connect_with(addr, Self::default_config(), SocketConfig::default()).await
+    }
+
+    /// Like [`Socket::connect`], with the queue sizes, transport
+    /// and handshake settings, the server name and the local
+    /// address from `config`
+    ///
+    /// the transport settings replace the ones in `client`
+    pub async fn connect_with<A: ToServerAddrs>(
+        addr: A,
+        mut client: ClientConfig,
+        config: SocketConfig,
+    ) -> Result<Self, ConnectError> {
+        client.transport = Arc::new(config.transport_config());
+        let mut socket = Self::connect_addrs(addr, client, &config).await?;
+        socket.flow_control = Some(config.flow_control);
+        Ok(socket)
+    }
+
+    /// the server name is taken from the host part of `addr`,
+    /// the transport settings of `config` are used
+    pub async fn connect_config<A: ToServerAddrs>(
+        addr: A,
+        config: ClientConfig,
+    ) -> Result<Self, ConnectError> {
+        Self::connect_addrs(addr, config, &SocketConfig::default()).await
+    }
+
+    // see `Socket::connect`
+    async fn connect_addrs<A: ToServerAddrs>(
+        addr: A,
+        client: ClientConfig,
+        config: &SocketConfig,
+    ) -> Result<Self, ConnectError> {
+        let server_name = &config
+            .server_name
+            .clone()
+            .unwrap_or_else(|| addr.server_name());
+        let addrs = resolve(&addr)
+            .await
             .map_err(ConnectError::InvalidSocketAddress)?;
 
-        attempt_all_async(
-            addrs,
-            move |addr| Self::connect_config(addr, config.clone()),
+        attempt_staggered(
+            interleave(addrs),
+            move |addr| {
+                Self::connect_session(
+                    addr,
+                    server_name,
+                    client.clone(),
+                    config,
+                    config.local.unwrap_or_else(|| unspecified(addr)),
+                    SessionHandshake::Disabled,
+                )
+            },
+            ATTEMPT_DELAY,
             ConnectError::NoSocketAddress,
         )
         .await
     }
 
-    pub async fn connect_config(
+    /// `server_name` is sent with SNI and
+    /// the server certificate is verified against it
+    ///
+    /// shortcut for [`Socket::connect_with`],
+    /// see [`SocketConfig::server_name`]
+    pub async fn connect_with_name(
         addr: SocketAddr,
+        server_name: &str,
         config: ClientConfig,
     ) -> Result<Self, ConnectError> {
-        // TODO: 1, see README.md
+        let socket = SocketConfig::default().with_server_name(Some(server_name.to_owned()));
+        Self::connect_with(addr, config, socket).await
+    }
 
-        let listen = if addr.is_ipv6() {
-            SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0).into()
-        } else {
-            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0).into()
-        };
+    /// `filter.payload` is sent to the server
+    /// and the server's handshake hook
+    /// can reject this client
+    ///
+    /// shortcut for [`Socket::connect_with`],
+    /// see [`SocketConfig::filter`]
+    pub async fn connect_with_filter(
+        addr: SocketAddr,
+        server_name: &str,
+        config: ClientConfig,
+        filter: &FilterConfig,
+    ) -> Result<Self, ConnectError> {
+        let socket = SocketConfig::default()
+            .with_server_name(Some(server_name.to_owned()))
+            .with_filter(filter.clone());
+        Self::connect_with(addr, config, socket).await
+    }
+
+    /// the client endpoint is bound to `local`,
+    /// to pick the interface or the port
+    ///
+    /// shortcut for [`Socket::connect_with`],
+    /// see [`SocketConfig::local`]
+    pub async fn connect_from(
+        addr: SocketAddr,
+        server_name: &str,
+        config: ClientConfig,
+        filter: &FilterConfig,
+        local: SocketAddr,
+    ) -> Result<Self, ConnectError> {
+        let socket = SocketConfig::default()
+            .with_server_name(Some(server_name.to_owned()))
+            .with_filter(filter.clone())
+            .with_local(Some(local));
+        Self::connect_with(addr, config, socket).await
+    }
 
-        let mut endpoint = Endpoint::client(listen)?;
+    /// the transport settings of `socket`
+    /// are not used, `config` has them
+    pub(crate) async fn connect_session(
+        addr: SocketAddr,
+        server_name: &str,
+        config: ClientConfig,
+        socket: &SocketConfig,
+        local: SocketAddr,
+        session: SessionHandshake<'_>,
+    ) -> Result<Self, ConnectError> {
+        let mut endpoint = Endpoint::client(local)?;
         endpoint.set_default_client_config(config);
-        let conn = endpoint.connect(addr, "localhost")?.await?;
+        Self::connect_endpoint(endpoint, addr, server_name, socket, session).await
+    }
 
-        Self::new(conn, endpoint).await
+    pub(crate) async fn connect_endpoint(
+        endpoint: Endpoint,
+        addr: SocketAddr,
+        server_name: &str,
+        config: &SocketConfig,
+        session: SessionHandshake<'_>,
+    ) -> Result<Self, ConnectError> {
+        let conn = endpoint
+            .connect(addr, server_name)?
+            .await
+            .map_err(handshake_error)?;
+
+        Self::new(conn, endpoint, config, Side::Client, session).await
     }
 
     /// Self signed certificate verifier
@@ -100,18 +300,78 @@ impl Socket {
             }
         }
 
+        Self::verifier_config(Arc::new(Verifier))
+    }
+
+    /// Accepts only the server certificate
+    /// with the given SHA-256 fingerprint
+    pub fn pinned_config(fingerprint: Fingerprint) -> ClientConfig {
+        Self::verifier_config(Arc::new(PinnedVerifier::new(fingerprint)))
+    }
+
+    /// Verifies the server certificate chain
+    /// and server name against `roots`
+    ///
+    /// see [`CaVerifier`]
+    pub fn ca_config(roots: RootCertStore) -> ClientConfig {
+        Self::verifier_config(Arc::new(CaVerifier::new(roots)))
+    }
+
+    pub fn verifier_config(verifier: Arc<dyn ServerCertVerifier>) -> ClientConfig {
         let crypto = rustls::ClientConfig::builder()
             .with_safe_defaults()
-            .with_custom_certificate_verifier(Arc::new(Verifier))
+            .with_custom_certificate_verifier(verifier)
             .with_no_client_auth();
         ClientConfig::new(Arc::new(crypto))
     }
 
+    /// [`Socket::verifier_config`] that also authenticates
+    /// this client to the server with a certificate
+    ///
+    /// see [`crate::listener::Listener::client_auth_config`]
+    pub fn client_cert_config(
+        verifier: Arc<dyn ServerCertVerifier>,
+        cert_chain: Vec<Certificate>,
+        priv_key: PrivateKey,
+    ) -> Result<ClientConfig, rustls::Error> {
+        let crypto = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(verifier)
+            .with_single_cert(cert_chain, priv_key)?;
+        Ok(ClientConfig::new(Arc::new(crypto)))
+    }
+
     /// panics if socket is split
     pub async fn recv(&mut self) -> Option<Packet> {
         self.receiver().recv().await
     }
 
+    /// Packets and the reason the connection
+    /// was closed, starts with [`SocketEvent::Connected`]
+    ///
+    /// keeps returning the disconnect
+    /// event after the connection is closed
+    ///
+    /// panics if socket is split
+    pub async fn next_event(&mut self) -> SocketEvent {
+        if !self.connected_reported {
+            self.connected_reported = true;
+            return SocketEvent::Connected;
+        }
+
+        if let Some(packet) = self.recv().await {
+            return SocketEvent::Packet(packet);
+        }
+
+        match self.disconnect_reason() {
+            Some(DisconnectReason::TimedOut) => SocketEvent::TimedOut,
+            Some(reason) => SocketEvent::Disconnected(reason),
+            None => SocketEvent::Disconnected(DisconnectReason::ConnectionLost(
+                quinn::ConnectionError::LocallyClosed,
+            )),
+        }
+    }
+
     /// panics if socket is split
     pub fn try_recv(&mut self) -> Result<Packet, TryRecvError> {
         self.receiver().try_recv()
@@ -161,6 +421,39 @@ impl Socket {
         &mut self.channels_mut().1
     }
 
+    /// Stops the socket and closes the
+    /// connection without waiting for
+    /// queued packets to be sent
+    ///
+    /// dropping the socket does
+    /// the same in the background
+    pub async fn close(mut self) {
+        if let Some(inner) = self.inner.take() {
+            inner.close().await;
+        }
+    }
+
+    /// Sends everything still queued, waits for the peer
+    /// to receive the reliable packets and then closes
+    /// the connection with `code` and `reason`
+    ///
+    /// the peer sees them in [`Socket::disconnect_reason`],
+    /// gives up waiting after the filter timeout
+    pub async fn disconnect(mut self, code: u32, reason: &str) {
+        if let Some(inner) = self.inner.take() {
+            inner.disconnect(code, reason).await;
+        }
+    }
+
+    /// `None` while the connection is open
+    /// or if it was closed locally
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        self.closed.reason()
+    }
+
+    /// waits for every connection of the endpoint,
+    /// sockets from the same [`crate::client::Client`]
+    /// or [`crate::listener::Listener`] share one
     pub async fn wait_idle(&self) {
         self.endpoint.wait_idle().await
     }
@@ -173,6 +466,29 @@ impl Socket {
         self.endpoint.local_addr().unwrap()
     }
 
+    /// Certificate chain the peer authenticated with
+    ///
+    /// on the server side this is only
+    /// available when client auth is used
+    pub fn peer_identity(&self) -> Option<Vec<Certificate>> {
+        peer_identity(&self.connection)
+    }
+
+    /// Fingerprint of the peer's end entity certificate
+    pub fn peer_fingerprint(&self) -> Option<Fingerprint> {
+        self.peer_identity()?.first().map(Fingerprint::of)
+    }
+
+    /// eznet version of the peer
+    pub fn peer_version(&self) -> Version {
+        self.peer_version
+    }
+
+    /// [`FilterConfig::payload`] of the peer
+    pub fn peer_payload(&self) -> &Bytes {
+        &self.peer_payload
+    }
+
     pub fn stats(&self) -> ConnectionStats {
         self.connection.stats()
     }
@@ -182,13 +498,240 @@ impl Socket {
         self.connection.rtt()
     }
 
-    pub(crate) async fn new(conn: NewConnection, endpoint: Endpoint) -> Result<Self, ConnectError> {
+    /// Congestion controller and windows in effect
+    ///
+    /// `None` if the transport settings are from a
+    /// [`ClientConfig`] or [`ServerConfig`](quinn::ServerConfig), quinn
+    /// doesn't tell which values they have
+    pub fn flow_control(&self) -> Option<FlowControl> {
+        self.flow_control
+    }
+
+    /// Current congestion window in bytes
+    pub fn congestion_window(&self) -> u64 {
+        self.stats().path.cwnd
+    }
+
+    /// Packets sent compressed and their
+    /// sizes before and after compression
+    pub fn compression_stats(&self) -> CompressionStats {
+        self.compression.stats()
+    }
+
+    pub(crate) async fn new(
+        conn: NewConnection,
+        endpoint: Endpoint,
+        config: &SocketConfig,
+        side: Side,
+        session: SessionHandshake<'_>,
+    ) -> Result<Self, ConnectError> {
         Ok(Self {
-            inner: Some(SocketInner::new(conn, endpoint).await?),
+            inner: Some(SocketInner::new(conn, endpoint, config, side, session).await?),
         })
     }
 }
 
+impl ToServerAddrs for SocketAddr {
+    fn server_name(&self) -> String {
+        self.ip().to_string()
+    }
+}
+
+impl ToServerAddrs for SocketAddrV4 {
+    fn server_name(&self) -> String {
+        self.ip().to_string()
+    }
+}
+
+impl ToServerAddrs for SocketAddrV6 {
+    fn server_name(&self) -> String {
+        self.ip().to_string()
+    }
+}
+
+impl ToServerAddrs for (IpAddr, u16) {
+    fn server_name(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl ToServerAddrs for (Ipv4Addr, u16) {
+    fn server_name(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl ToServerAddrs for (Ipv6Addr, u16) {
+    fn server_name(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl ToServerAddrs for &[SocketAddr] {
+    fn server_name(&self) -> String {
+        self.first()
+            .map_or_else(String::new, |addr| addr.ip().to_string())
+    }
+}
+
+impl ToServerAddrs for (&str, u16) {
+    fn server_name(&self) -> String {
+        self.0.to_owned()
+    }
+
+    fn host_port(&self) -> Option<(String, u16)> {
+        Some((self.0.to_owned(), self.1))
+    }
+}
+
+impl ToServerAddrs for (String, u16) {
+    fn server_name(&self) -> String {
+        self.0.clone()
+    }
+
+    fn host_port(&self) -> Option<(String, u16)> {
+        Some(self.clone())
+    }
+}
+
+impl ToServerAddrs for str {
+    fn server_name(&self) -> String {
+        host(self).to_owned()
+    }
+
+    fn host_port(&self) -> Option<(String, u16)> {
+        host_port(self)
+    }
+}
+
+impl ToServerAddrs for String {
+    fn server_name(&self) -> String {
+        host(self).to_owned()
+    }
+
+    fn host_port(&self) -> Option<(String, u16)> {
+        host_port(self)
+    }
+}
+
+impl<T: ToServerAddrs + ?Sized> ToServerAddrs for &T {
+    fn server_name(&self) -> String {
+        (**self).server_name()
+    }
+
+    fn host_port(&self) -> Option<(String, u16)> {
+        (**self).host_port()
+    }
+}
+
+//
+
+// host part of `host:port` or `[host]:port`
+fn host(addr: &str) -> &str {
+    if let Some(addr) = addr.strip_prefix('[') {
+        addr.split_once(']').map_or(addr, |(host, _)| host)
+    } else {
+        addr.rsplit_once(':').map_or(addr, |(host, _)| host)
+    }
+}
+
+// `host:port` or `[host]:port`
+fn host_port(addr: &str) -> Option<(String, u16)> {
+    let (_, port) = addr.rsplit_once(':')?;
+    Some((host(addr).to_owned(), port.parse().ok()?))
+}
+
+// DNS lookups run on the blocking thread pool
+pub(crate) async fn resolve<A: ToServerAddrs>(addr: &A) -> io::Result<Vec<SocketAddr>> {
+    match addr.host_port() {
+        Some(host_port) => Ok(lookup_host(host_port).await?.collect()),
+        None => Ok(addr.to_socket_addrs()?.collect()),
+    }
+}
+
+// any local address of the same family as `remote`
+pub(crate) fn unspecified(remote: SocketAddr) -> SocketAddr {
+    if remote.is_ipv6() {
+        SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0).into()
+    } else {
+        SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into()
+    }
+}
+
+fn peer_identity(connection: &quinn::Connection) -> Option<Vec<Certificate>> {
+    connection
+        .peer_identity()?
+        .downcast::<Vec<Certificate>>()
+        .ok()
+        .map(|chain| *chain)
+}
+
+// the certificate verifier rejecting the
+// server shows up as a bad_certificate alert
+//
+// QUIC maps TLS alerts to 0x100 + alert
+fn handshake_error(err: quinn::ConnectionError) -> ConnectError {
+    match err {
+        quinn::ConnectionError::TransportError(err) if u64::from(err.code) == BAD_CERTIFICATE => {
+            // only the message of the verifier error is kept
+            match err.reason.split_once(cert::SERVER_NAME_MISMATCH) {
+                Some((_, message)) => ConnectError::ServerNameMismatch(message.to_owned()),
+                None => ConnectError::CertificateRejected(err.reason),
+            }
+        }
+        err => err.into(),
+    }
+}
+
+impl ConnectError {
+    /// timeouts and lost connections, trying
+    /// again later might work
+    pub(crate) fn is_transient(&self) -> bool {
+        match self {
+            Self::Connection(quinn::ConnectionError::VersionMismatch) => false,
+            Self::Connection(_) | Self::IoError(_) => true,
+            Self::FilterError(err) => matches!(
+                err,
+                FilterError::TimedOut
+                    | FilterError::ConnectionError(_)
+                    | FilterError::IoError(_)
+                    | FilterError::WriteError(_)
+                    | FilterError::MissingVerdict
+            ),
+            _ => false,
+        }
+    }
+}
+
+// TLS bad_certificate alert (RFC 8446 6.2)
+const BAD_CERTIFICATE: u64 = 0x100 | 42;
+
+// application close code for connections
+// closed because of a malformed packet
+// or a failed write
+pub(crate) const INTERNAL_ERROR: VarInt = VarInt::from_u32(2);
+
+// codes of `Socket::disconnect` are sent after
+// this, the ones below are reserved for eznet
+pub(crate) const APPLICATION_CODES: u64 = 1 << 32;
+
+impl From<quinn::ConnectionError> for DisconnectReason {
+    fn from(err: quinn::ConnectionError) -> Self {
+        match err {
+            quinn::ConnectionError::TimedOut => Self::TimedOut,
+            quinn::ConnectionError::ApplicationClosed(close)
+                if close.error_code.into_inner() >= APPLICATION_CODES =>
+            {
+                Self::Disconnected {
+                    code: (close.error_code.into_inner() - APPLICATION_CODES) as u32,
+                    reason: String::from_utf8_lossy(&close.reason).into_owned(),
+                }
+            }
+            err => Self::ConnectionLost(err),
+        }
+    }
+}
+
 impl Deref for Socket {
     type Target = SocketInner;
 
@@ -205,8 +748,318 @@ impl DerefMut for Socket {
 
 impl Drop for Socket {
     fn drop(&mut self) {
+        // blocking here could deadlock the runtime
         if let Some(s) = self.inner.take() {
-            s.close();
+            s.close_in_background();
+        }
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cert::{generate_self_signed, server_name_error},
+        config::{Congestion, FlowControl, ListenerConfig, SocketConfig, Timeouts},
+        filter::FilterConfig,
+        listener::Listener,
+        packet::Packet,
+        socket::{
+            ConnectError, DisconnectReason, Socket, SocketEvent, ToServerAddrs, INTERNAL_ERROR,
+        },
+        wire::WireError,
+    };
+    use bytes::Bytes;
+    use rustls::{
+        client::{ServerCertVerified, ServerCertVerifier},
+        Certificate, RootCertStore, ServerName,
+    };
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, SystemTime},
+    };
+    use tokio::net::UdpSocket;
+
+    #[test]
+    fn server_name_test() {
+        assert_eq!("game.example.com:13331".server_name(), "game.example.com");
+        assert_eq!("127.0.0.1:13331".server_name(), "127.0.0.1");
+        assert_eq!("[::1]:13331".server_name(), "::1");
+        assert_eq!(("localhost", 13331).server_name(), "localhost");
+
+        let addr: SocketAddr = "[::1]:13331".parse().unwrap();
+        assert_eq!(addr.server_name(), "::1");
+
+        assert_eq!("[::1]:13331".host_port(), Some(("::1".to_owned(), 13331)));
+        assert_eq!("localhost".host_port(), None);
+        assert_eq!(addr.host_port(), None);
+
+        assert_eq!((Ipv4Addr::LOCALHOST, 13331).server_name(), "127.0.0.1");
+        assert_eq!((Ipv6Addr::LOCALHOST, 13331).server_name(), "::1");
+        assert_eq!((&[addr][..]).server_name(), "::1");
+    }
+
+    #[tokio::test]
+    async fn ca_config_test() {
+        // the certificate is for `localhost`
+        let (cert_chain, priv_key) = generate_self_signed().unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(&cert_chain[0]).unwrap();
+
+        let mut listener = Listener::bind_cert("127.0.0.1:0", cert_chain, priv_key).unwrap();
+        let port = listener.local_addrs()[0].port();
+        tokio::spawn(async move {
+            loop {
+                let _ = listener.next().await;
+            }
+        });
+
+        let config = Socket::ca_config(roots);
+        Socket::connect_config(("localhost", port), config.clone())
+            .await
+            .unwrap();
+
+        for server_name in ["127.0.0.1", "example.com"] {
+            let result = Socket::connect_with_name(
+                (Ipv4Addr::LOCALHOST, port).into(),
+                server_name,
+                config.clone(),
+            )
+            .await;
+            assert!(
+                matches!(result, Err(ConnectError::ServerNameMismatch(_))),
+                "{result:?}"
+            );
         }
+
+        // the configured name wins over the host
+        let socket = SocketConfig::default().with_server_name(Some("example.com".to_owned()));
+        assert!(matches!(
+            Socket::connect_with(("localhost", port), config.clone(), socket).await,
+            Err(ConnectError::ServerNameMismatch(_))
+        ));
+        let socket = SocketConfig::default().with_local(Some((Ipv4Addr::LOCALHOST, 0).into()));
+        let client = Socket::connect_with(("localhost", port), config.clone(), socket)
+            .await
+            .unwrap();
+        assert_eq!(client.local().ip(), Ipv4Addr::LOCALHOST);
+
+        // custom verifiers tag their name errors
+        struct NameVerifier;
+        impl ServerCertVerifier for NameVerifier {
+            fn verify_server_cert(
+                &self,
+                _end_entity: &Certificate,
+                _intermediates: &[Certificate],
+                _server_name: &ServerName,
+                _scts: &mut dyn Iterator<Item = &[u8]>,
+                _ocsp_response: &[u8],
+                _now: SystemTime,
+            ) -> Result<ServerCertVerified, rustls::Error> {
+                Err(server_name_error("not my name"))
+            }
+        }
+        let config = Socket::verifier_config(Arc::new(NameVerifier));
+        assert!(matches!(
+            Socket::connect_config(("localhost", port), config).await,
+            Err(ConnectError::ServerNameMismatch(reason)) if reason == "not my name"
+        ));
+    }
+
+    async fn pair() -> (Socket, Socket) {
+        let mut listener = Listener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addrs()[0];
+        let (server, client) = tokio::join!(listener.next(), Socket::connect(addr));
+        (server.unwrap(), client.unwrap())
+    }
+
+    #[tokio::test]
+    async fn disconnect_event_test() {
+        let (server, mut client) = pair().await;
+        server.send(Packet::ordered("hi", None)).await.unwrap();
+        server.disconnect(7, "bye").await;
+
+        assert_eq!(client.next_event().await, SocketEvent::Connected);
+        assert_eq!(
+            client.next_event().await,
+            SocketEvent::Packet(Packet::ordered("hi", None))
+        );
+        let reason = DisconnectReason::Disconnected {
+            code: 7,
+            reason: "bye".to_owned(),
+        };
+        assert_eq!(
+            client.next_event().await,
+            SocketEvent::Disconnected(reason.clone())
+        );
+        assert_eq!(client.disconnect_reason(), Some(reason));
+    }
+
+    #[tokio::test]
+    async fn malformed_packet_event_test() {
+        let (mut server, mut client) = pair().await;
+        client
+            .connection
+            .send_datagram(Bytes::from_static(&[0x07]))
+            .unwrap();
+
+        let reason = DisconnectReason::MalformedPacket(WireError::InvalidKind(7).to_string());
+        assert_eq!(server.next_event().await, SocketEvent::Connected);
+        assert_eq!(
+            server.next_event().await,
+            SocketEvent::Disconnected(reason.clone())
+        );
+
+        assert_eq!(client.next_event().await, SocketEvent::Connected);
+        assert!(matches!(
+            client.next_event().await,
+            SocketEvent::Disconnected(DisconnectReason::ConnectionLost(
+                quinn::ConnectionError::ApplicationClosed(close)
+            )) if close.error_code == INTERNAL_ERROR && close.reason == reason.to_string()
+        ));
+    }
+
+    #[tokio::test]
+    async fn reserved_code_test() {
+        let mut listener = Listener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addrs()[0];
+        tokio::spawn(async move {
+            while let Ok(socket) = listener.next().await {
+                socket.disconnect(1, "kicked").await;
+            }
+        });
+
+        let mut client = Socket::connect(addr).await.unwrap();
+        assert_eq!(client.next_event().await, SocketEvent::Connected);
+        assert_eq!(
+            client.next_event().await,
+            SocketEvent::Disconnected(DisconnectReason::Disconnected {
+                code: 1,
+                reason: "kicked".to_owned(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_test() {
+        let config = SocketConfig::default()
+            .with_timeouts(Timeouts::default().with_idle_timeout(Some(Duration::from_millis(300))));
+        let mut listener = Listener::bind_with(
+            "127.0.0.1:0",
+            Listener::default_config().unwrap(),
+            ListenerConfig::default().with_socket(config.clone()),
+        )
+        .unwrap();
+        let addr = listener.local_addrs()[0];
+        let (server, client) = tokio::join!(
+            listener.next(),
+            Socket::connect_with(addr, Socket::default_config(), config)
+        );
+        let (_server, mut client) = (server.unwrap(), client.unwrap());
+
+        // neither side sends anything without keep-alive
+        assert_eq!(client.next_event().await, SocketEvent::Connected);
+        assert_eq!(client.next_event().await, SocketEvent::TimedOut);
+        assert_eq!(client.disconnect_reason(), Some(DisconnectReason::TimedOut));
+    }
+
+    #[tokio::test]
+    async fn disconnect_dead_peer_test() {
+        let config = SocketConfig::default()
+            .with_timeouts(Timeouts::default().with_idle_timeout(None))
+            .with_filter(FilterConfig::default().with_timeout(Duration::from_millis(200)));
+        let mut listener = Listener::bind_with(
+            "127.0.0.1:0",
+            Listener::default_config().unwrap(),
+            ListenerConfig::default().with_socket(config.clone()),
+        )
+        .unwrap();
+        let dead = Arc::new(AtomicBool::new(false));
+        let addr = proxy(listener.local_addrs()[0], dead.clone()).await;
+        let (server, client) = tokio::join!(
+            listener.next(),
+            Socket::connect_with(addr, Socket::default_config(), config)
+        );
+        let (server, _client) = (server.unwrap(), client.unwrap());
+
+        // nothing gets acknowledged anymore
+        dead.store(true, Ordering::SeqCst);
+        server.send(Packet::ordered("hi", None)).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server.disconnect(7, "bye"))
+            .await
+            .unwrap();
+    }
+
+    // forwards packets between the client and
+    // `server` until `dead` is set
+    async fn proxy(server: SocketAddr, dead: Arc<AtomicBool>) -> SocketAddr {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let upstream = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        upstream.connect(server).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (client_addr, mut client_addr_recv) = tokio::sync::watch::channel(None);
+
+        let (from, to, from_dead) = (socket.clone(), upstream.clone(), dead.clone());
+        tokio::spawn(async move {
+            let mut buf = [0; 2048];
+            while let Ok((len, client)) = from.recv_from(&mut buf).await {
+                let _ = client_addr.send(Some(client));
+                if !from_dead.load(Ordering::SeqCst) {
+                    let _ = to.send(&buf[..len]).await;
+                }
+            }
+        });
+        tokio::spawn(async move {
+            let mut buf = [0; 2048];
+            let _ = client_addr_recv.changed().await;
+            let client = client_addr_recv.borrow().unwrap();
+            while let Ok(len) = upstream.recv(&mut buf).await {
+                if !dead.load(Ordering::SeqCst) {
+                    let _ = socket.send_to(&buf[..len], client).await;
+                }
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn flow_control_test() {
+        let flow_control = FlowControl::default()
+            .with_congestion(Congestion::NewReno)
+            .with_initial_window(Some(64 * 1024))
+            .with_send_window(1_000_000);
+        let config = SocketConfig::default().with_flow_control(flow_control);
+        let mut listener = Listener::bind_with(
+            "127.0.0.1:0",
+            Listener::default_config().unwrap(),
+            ListenerConfig::default().with_socket(config.clone()),
+        )
+        .unwrap();
+        let addr = listener.local_addrs()[0];
+
+        let (server, client) = tokio::join!(
+            listener.next(),
+            Socket::connect_with(addr, Socket::default_config(), config)
+        );
+        assert_eq!(server.unwrap().flow_control(), Some(flow_control));
+        assert_eq!(client.unwrap().flow_control(), Some(flow_control));
+
+        // the transport settings of these configs are unknown
+        listener.set_config(Listener::default_config().unwrap());
+        let (server, client) = tokio::join!(
+            listener.next(),
+            Socket::connect_config(addr, Socket::default_config())
+        );
+        assert_eq!(server.unwrap().flow_control(), None);
+        assert_eq!(client.unwrap().flow_control(), None);
+
+        let (server, client) = pair().await;
+        assert_eq!(server.flow_control(), Some(FlowControl::default()));
+        assert_eq!(client.flow_control(), Some(FlowControl::default()));
     }
 }
@@ -0,0 +1,222 @@
+use crate::{
+    packet::{Packet, PacketHeader},
+    socket::Socket,
+};
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{error::Error, marker::PhantomData};
+use thiserror::Error;
+
+//
+
+/// Serialization format of a [`TypedSocket`]
+///
+/// both peers have to use the same one
+pub trait Codec {
+    fn encode<T: Serialize>(message: &T) -> Result<Bytes, CodecError>;
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+#[derive(Debug, Error)]
+pub enum CodecError {
+    #[error("failed to encode message ({0})")]
+    Encode(Box<dyn Error + Send + Sync>),
+
+    #[error("failed to decode message ({0})")]
+    Decode(Box<dyn Error + Send + Sync>),
+}
+
+#[derive(Debug, Error)]
+pub enum TypedSendError {
+    #[error(transparent)]
+    Codec(#[from] CodecError),
+
+    #[error("socket closed")]
+    Closed,
+}
+
+/// [`bincode`], the default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+/// [`postcard`]
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Postcard;
+
+/// [`serde_json`]
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+/// [`rmp_serde`], structs are maps with field names
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+/// Socket that sends `Out` and receives `In` messages
+///
+/// messages are encoded with `C`, see [`Codec`]
+pub struct TypedSocket<In, Out, C = Bincode> {
+    socket: Socket,
+    _marker: PhantomData<fn(Out) -> (In, C)>,
+}
+
+//
+
+impl<In, Out, C> TypedSocket<In, Out, C>
+where
+    In: DeserializeOwned,
+    Out: Serialize,
+    C: Codec,
+{
+    pub fn new(socket: Socket) -> Self {
+        Self {
+            socket,
+            _marker: PhantomData,
+        }
+    }
+
+    /// `None` if the socket is closed
+    ///
+    /// messages that fail to decode are
+    /// returned as errors and skipped
+    pub async fn recv(&mut self) -> Option<Result<In, CodecError>> {
+        let packet = self.socket.recv().await?;
+        Some(C::decode(&packet.bytes))
+    }
+
+    /// reliable and ordered, see [`Packet::ordered`]
+    pub async fn send(&self, message: &Out) -> Result<(), TypedSendError> {
+        self.send_with(message, PacketHeader::default()).await
+    }
+
+    /// `header` picks the reliability and ordering
+    pub async fn send_with(
+        &self,
+        message: &Out,
+        header: PacketHeader,
+    ) -> Result<(), TypedSendError> {
+        let packet = Packet {
+            header,
+            bytes: C::encode(message)?,
+            compression: None,
+        };
+        self.socket.send(packet).await.ok_or(TypedSendError::Closed)
+    }
+
+    pub fn socket(&self) -> &Socket {
+        &self.socket
+    }
+
+    pub fn socket_mut(&mut self) -> &mut Socket {
+        &mut self.socket
+    }
+
+    pub fn into_inner(self) -> Socket {
+        self.socket
+    }
+}
+
+impl Socket {
+    /// Messages encoded with [`Bincode`]
+    ///
+    /// use [`TypedSocket::new`] for other codecs
+    pub fn typed<In, Out>(self) -> TypedSocket<In, Out>
+    where
+        In: DeserializeOwned,
+        Out: Serialize,
+    {
+        TypedSocket::new(self)
+    }
+}
+
+impl Codec for Bincode {
+    fn encode<T: Serialize>(message: &T) -> Result<Bytes, CodecError> {
+        bincode::serialize(message)
+            .map(Bytes::from)
+            .map_err(|err| CodecError::Encode(err))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        bincode::deserialize(bytes).map_err(|err| CodecError::Decode(err))
+    }
+}
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    fn encode<T: Serialize>(message: &T) -> Result<Bytes, CodecError> {
+        postcard::to_allocvec(message)
+            .map(Bytes::from)
+            .map_err(|err| CodecError::Encode(err.into()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        postcard::from_bytes(bytes).map_err(|err| CodecError::Decode(err.into()))
+    }
+}
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn encode<T: Serialize>(message: &T) -> Result<Bytes, CodecError> {
+        serde_json::to_vec(message)
+            .map(Bytes::from)
+            .map_err(|err| CodecError::Encode(err.into()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        serde_json::from_slice(bytes).map_err(|err| CodecError::Decode(err.into()))
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<T: Serialize>(message: &T) -> Result<Bytes, CodecError> {
+        rmp_serde::to_vec_named(message)
+            .map(Bytes::from)
+            .map_err(|err| CodecError::Encode(err.into()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        rmp_serde::from_slice(bytes).map_err(|err| CodecError::Decode(err.into()))
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::typed::{Bincode, Codec, CodecError};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Message {
+        id: u32,
+        text: String,
+    }
+
+    fn round_trip<C: Codec>() {
+        let message = Message {
+            id: 7,
+            text: "hello".to_owned(),
+        };
+        let bytes = C::encode(&message).unwrap();
+        assert_eq!(C::decode::<Message>(&bytes).unwrap(), message);
+        assert!(matches!(
+            C::decode::<Message>(&bytes[..2]),
+            Err(CodecError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn codec_test() {
+        round_trip::<Bincode>();
+        #[cfg(feature = "postcard")]
+        round_trip::<crate::typed::Postcard>();
+        #[cfg(feature = "json")]
+        round_trip::<crate::typed::Json>();
+        #[cfg(feature = "msgpack")]
+        round_trip::<crate::typed::MessagePack>();
+    }
+}
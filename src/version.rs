@@ -0,0 +1,141 @@
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+//
+
+/// eznet crate version
+///
+/// used in the filter handshake to
+/// reject incompatible peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+#[derive(Debug, Error)]
+#[error("invalid version '{0}'")]
+pub struct VersionParseError(pub String);
+
+//
+
+/// Versions that broke the wire format
+/// without a semver incompatible bump
+///
+/// peers on different sides of any of
+/// these versions are not compatible
+pub static BREAKING_VERSIONS: &[Version] = &[];
+
+//
+
+impl Version {
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// version of this crate
+    pub fn current() -> Self {
+        // CARGO_PKG_VERSION is validated by cargo
+        env!("CARGO_PKG_VERSION").parse().unwrap()
+    }
+
+    /// semver compatible and not separated
+    /// by any of the [`BREAKING_VERSIONS`]
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.is_compatible_with(other, BREAKING_VERSIONS)
+    }
+
+    fn is_compatible_with(&self, other: &Self, breaking: &[Version]) -> bool {
+        let semver = if self.major == 0 {
+            self.major == other.major && self.minor == other.minor
+        } else {
+            self.major == other.major
+        };
+
+        let (older, newer) = if self < other {
+            (self, other)
+        } else {
+            (other, self)
+        };
+
+        semver && !breaking.iter().any(|b| older < b && b <= newer)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for Version {
+    type Err = VersionParseError;
+
+    /// accepts `1.2.3` and `eznet-1.2.3`
+    ///
+    /// pre-release and build metadata are ignored
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || VersionParseError(s.to_owned());
+
+        let version = s
+            .strip_prefix(concat!(env!("CARGO_PKG_NAME"), "-"))
+            .unwrap_or(s);
+        let version = version.split(['-', '+']).next().ok_or_else(err)?;
+
+        let mut parts = version.split('.').map(|part| part.parse::<u16>());
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) => {
+                Ok(Self::new(major, minor, patch))
+            }
+            _ => Err(err()),
+        }
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::version::Version;
+
+    #[test]
+    fn version_parse_test() {
+        assert_eq!("0.2.0".parse::<Version>().unwrap(), Version::new(0, 2, 0));
+        assert_eq!(
+            "eznet-1.12.3".parse::<Version>().unwrap(),
+            Version::new(1, 12, 3)
+        );
+        assert_eq!(
+            "1.0.0-alpha.1+build".parse::<Version>().unwrap(),
+            Version::new(1, 0, 0)
+        );
+        assert!("1.0".parse::<Version>().is_err());
+        assert!("1.0.0.0".parse::<Version>().is_err());
+        assert!("other-1.0.0".parse::<Version>().is_err());
+        assert_eq!(
+            crate::VERSION.parse::<Version>().unwrap(),
+            Version::current()
+        );
+    }
+
+    #[test]
+    fn version_compatible_test() {
+        let v = Version::new;
+
+        assert!(v(0, 2, 0).is_compatible_with(&v(0, 2, 5), &[]));
+        assert!(!v(0, 2, 0).is_compatible_with(&v(0, 3, 0), &[]));
+        assert!(v(1, 0, 0).is_compatible_with(&v(1, 4, 2), &[]));
+        assert!(!v(1, 0, 0).is_compatible_with(&v(2, 0, 0), &[]));
+
+        let breaking = [v(0, 2, 3)];
+        assert!(v(0, 2, 0).is_compatible_with(&v(0, 2, 2), &breaking));
+        assert!(!v(0, 2, 2).is_compatible_with(&v(0, 2, 3), &breaking));
+        assert!(!v(0, 2, 5).is_compatible_with(&v(0, 2, 1), &breaking));
+        assert!(v(0, 2, 3).is_compatible_with(&v(0, 2, 5), &breaking));
+    }
+}
@@ -0,0 +1,276 @@
+//! Wire format of a [`Packet`]
+//!
+//! every packet is a header followed by the payload,
+//! the payload runs to the end of the QUIC datagram
+//! or the length delimited stream frame
+//!
+//! | field   | size    | present                         |
+//! |---------|---------|---------------------------------|
+//! | kind    | 1 byte  | always                          |
+//! | channel | 1 byte  | if [`CHANNEL`] is set           |
+//! | seq     | 1-3     | for the two sequenced kinds     |
+//!
+//! the low 3 bits of the kind byte are the [`PacketHeader`]:
+//! `0` ordered, `1` reliable sequenced, `2` reliable unordered,
+//! `3` unreliable sequenced and `4` unreliable
+//!
+//! the upper bits are flags, unknown flags are rejected
+//!
+//! `seq` is an unsigned LEB128 varint of the `seq_id`
+//!
+//! the [`COMPRESSION`] bits are the algorithm of the payload:
+//! `0` raw, `1` lz4 (the uncompressed length as a little
+//! endian u32 and an lz4 block) and `2` a zstd frame,
+//! payloads decompress to at most [`MAX_PAYLOAD`] bytes
+//!
+//! peers exchange [`WIRE_VERSION`] and the algorithms
+//! they can decompress in the filter handshake
+
+use crate::{
+    compression::Compression,
+    packet::{Packet, PacketHeader},
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use thiserror::Error;
+
+//
+
+/// version of this format, bumped
+/// on every incompatible change
+pub const WIRE_VERSION: u8 = 1;
+
+/// flag of the kind byte, the `stream_id`
+/// follows as the channel byte
+pub const CHANNEL: u8 = 0b1000;
+
+/// flag bits of the kind byte, the
+/// compression algorithm of the payload
+pub const COMPRESSION: u8 = 0b11_0000;
+
+/// the length delimited frames of
+/// streams can't be larger either
+pub const MAX_PAYLOAD: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum WireError {
+    #[error("empty packet")]
+    Empty,
+
+    #[error("invalid packet kind {0}")]
+    InvalidKind(u8),
+
+    #[error("invalid packet flags {0:#04x}")]
+    InvalidFlags(u8),
+
+    #[error("truncated packet header")]
+    Truncated,
+
+    #[error("packet seq_id out of range")]
+    InvalidSeq,
+
+    #[error("unsupported packet compression {0}")]
+    UnsupportedCompression(u8),
+
+    #[error("failed to decompress packet ({0})")]
+    Decompress(String),
+}
+
+//
+
+/// raw, see [`Packet::with_compression`]
+/// for compressed packets
+pub fn encode(packet: &Packet) -> Bytes {
+    encode_with(packet.header, Compression::None, &packet.bytes)
+}
+
+/// `payload` is already compressed with `compression`
+pub(crate) fn encode_with(header: PacketHeader, compression: Compression, payload: &[u8]) -> Bytes {
+    let (kind, stream_id, seq_id) = match header {
+        PacketHeader::Ordered { stream_id } => (ORDERED, stream_id, None),
+        PacketHeader::ReliableSequenced { stream_id, seq_id } => {
+            (RELIABLE_SEQUENCED, stream_id, Some(seq_id))
+        }
+        PacketHeader::ReliableUnordered => (RELIABLE_UNORDERED, None, None),
+        PacketHeader::UnreliableSequenced { stream_id, seq_id } => {
+            (UNRELIABLE_SEQUENCED, stream_id, Some(seq_id))
+        }
+        PacketHeader::Unreliable => (UNRELIABLE, None, None),
+    };
+
+    let flags = stream_id.map_or(0, |_| CHANNEL) | compression.id() << 4;
+
+    let mut bytes = BytesMut::with_capacity(MAX_HEADER + payload.len());
+    bytes.put_u8(kind | flags);
+    if let Some(stream_id) = stream_id {
+        bytes.put_u8(stream_id);
+    }
+    if let Some(mut seq_id) = seq_id {
+        while seq_id >= 0x80 {
+            bytes.put_u8(seq_id as u8 | 0x80);
+            seq_id >>= 7;
+        }
+        bytes.put_u8(seq_id as u8);
+    }
+    bytes.put_slice(payload);
+    bytes.freeze()
+}
+
+pub fn decode(mut bytes: Bytes) -> Result<Packet, WireError> {
+    if bytes.is_empty() {
+        return Err(WireError::Empty);
+    }
+    let byte = bytes.get_u8();
+    let (kind, flags) = (byte & KIND, byte & !KIND);
+
+    if flags & !(CHANNEL | COMPRESSION) != 0 {
+        return Err(WireError::InvalidFlags(flags));
+    }
+    let compression = Compression::from_id((flags & COMPRESSION) >> 4)?;
+
+    let stream_id = if flags & CHANNEL != 0 {
+        if !matches!(kind, ORDERED | RELIABLE_SEQUENCED | UNRELIABLE_SEQUENCED) {
+            return Err(WireError::InvalidFlags(flags & CHANNEL));
+        }
+        if bytes.is_empty() {
+            return Err(WireError::Truncated);
+        }
+        Some(bytes.get_u8())
+    } else {
+        None
+    };
+
+    let header = match kind {
+        ORDERED => PacketHeader::Ordered { stream_id },
+        RELIABLE_SEQUENCED => PacketHeader::ReliableSequenced {
+            stream_id,
+            seq_id: decode_seq(&mut bytes)?,
+        },
+        RELIABLE_UNORDERED => PacketHeader::ReliableUnordered,
+        UNRELIABLE_SEQUENCED => PacketHeader::UnreliableSequenced {
+            stream_id,
+            seq_id: decode_seq(&mut bytes)?,
+        },
+        UNRELIABLE => PacketHeader::Unreliable,
+        kind => return Err(WireError::InvalidKind(kind)),
+    };
+
+    Ok(Packet {
+        header,
+        bytes: compression.decompress(bytes, MAX_PAYLOAD)?,
+        compression: None,
+    })
+}
+
+//
+
+fn decode_seq(bytes: &mut Bytes) -> Result<u16, WireError> {
+    let mut seq_id = 0u32;
+    for shift in [0, 7, 14] {
+        if bytes.is_empty() {
+            return Err(WireError::Truncated);
+        }
+        let byte = bytes.get_u8();
+        seq_id |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return u16::try_from(seq_id).map_err(|_| WireError::InvalidSeq);
+        }
+    }
+    Err(WireError::InvalidSeq)
+}
+
+const KIND: u8 = 0b111;
+
+const ORDERED: u8 = 0;
+const RELIABLE_SEQUENCED: u8 = 1;
+const RELIABLE_UNORDERED: u8 = 2;
+const UNRELIABLE_SEQUENCED: u8 = 3;
+const UNRELIABLE: u8 = 4;
+
+// kind, channel and a 3 byte seq
+const MAX_HEADER: usize = 5;
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        packet::{Packet, PacketHeader},
+        wire::{decode, encode, WireError},
+    };
+    use bytes::Bytes;
+
+    #[test]
+    fn wire_golden_test() {
+        let golden: [(PacketHeader, &[u8]); 8] = [
+            (PacketHeader::Ordered { stream_id: None }, &[0x00]),
+            (PacketHeader::Ordered { stream_id: Some(7) }, &[0x08, 0x07]),
+            (
+                PacketHeader::ReliableSequenced {
+                    stream_id: None,
+                    seq_id: 5,
+                },
+                &[0x01, 0x05],
+            ),
+            (
+                PacketHeader::ReliableSequenced {
+                    stream_id: Some(1),
+                    seq_id: 300,
+                },
+                &[0x09, 0x01, 0xac, 0x02],
+            ),
+            (PacketHeader::ReliableUnordered, &[0x02]),
+            (
+                PacketHeader::UnreliableSequenced {
+                    stream_id: None,
+                    seq_id: 127,
+                },
+                &[0x03, 0x7f],
+            ),
+            (
+                PacketHeader::UnreliableSequenced {
+                    stream_id: Some(255),
+                    seq_id: u16::MAX,
+                },
+                &[0x0b, 0xff, 0xff, 0xff, 0x03],
+            ),
+            (PacketHeader::Unreliable, &[0x04]),
+        ];
+
+        for (header, expected) in golden {
+            let packet = Packet {
+                header,
+                bytes: Bytes::from_static(b"hi"),
+                compression: None,
+            };
+            let bytes = encode(&packet);
+            assert_eq!(&bytes[..bytes.len() - 2], expected, "{header:?}");
+            assert_eq!(&bytes[bytes.len() - 2..], b"hi");
+            assert_eq!(decode(bytes).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn wire_invalid_test() {
+        let decode = |bytes: &'static [u8]| decode(Bytes::from_static(bytes));
+
+        assert_eq!(decode(&[]), Err(WireError::Empty));
+        assert_eq!(decode(&[0x05]), Err(WireError::InvalidKind(5)));
+        assert_eq!(decode(&[0x40]), Err(WireError::InvalidFlags(0x40)));
+        assert_eq!(decode(&[0x30]), Err(WireError::UnsupportedCompression(3)));
+        #[cfg(not(feature = "lz4"))]
+        assert_eq!(decode(&[0x10]), Err(WireError::UnsupportedCompression(1)));
+        #[cfg(not(feature = "zstd"))]
+        assert_eq!(decode(&[0x20]), Err(WireError::UnsupportedCompression(2)));
+        assert_eq!(decode(&[0x0c, 0x01]), Err(WireError::InvalidFlags(0x08)));
+        assert_eq!(decode(&[0x08]), Err(WireError::Truncated));
+        assert_eq!(decode(&[0x01, 0x80]), Err(WireError::Truncated));
+        assert_eq!(
+            decode(&[0x01, 0xff, 0xff, 0x04]),
+            Err(WireError::InvalidSeq)
+        );
+        assert_eq!(
+            decode(&[0x01, 0x80, 0x80, 0x80, 0x00]),
+            Err(WireError::InvalidSeq)
+        );
+    }
+}
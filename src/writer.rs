@@ -1,22 +1,27 @@
 use crate::{
+    compression::{Compression, Compressor},
+    inner::Closed,
     packet::{Packet, PacketHeader},
+    socket::DisconnectReason,
     unwrap_or,
 };
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::Bytes;
 use futures::{
     future::{join_all, pending},
     select_biased, FutureExt, SinkExt,
 };
-use quinn::{Connection, SendStream};
+use quinn::{Connection, ConnectionError, SendDatagramError, SendStream, WriteError};
 use std::{
     collections::{hash_map::Entry, HashMap},
+    error::Error,
+    io,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
 };
 use tokio::{
-    sync::{broadcast, mpsc},
+    sync::{broadcast, mpsc, oneshot},
     time::{sleep_until, Duration, Instant},
 };
 use tokio_util::codec::{FramedWrite, LengthDelimitedCodec};
@@ -27,23 +32,35 @@ pub async fn writer_worker_job(
     connection: Connection,
     mut recv: mpsc::Receiver<Packet>,
     mut should_stop: broadcast::Receiver<()>,
+    drain: oneshot::Receiver<()>,
+    closed: Closed,
+    flush_interval: Duration,
+    compressor: Compressor,
 ) {
     let mut ordered: HashMap<Option<u8>, FWrite> = Default::default();
     let mut can_flush = false;
 
+    // every unordered stream holds a clone until it
+    // is finished, the receiver sees `None` after that
+    let (unordered, mut unordered_done) = mpsc::channel::<()>(1);
+    let mut drain = Some(drain);
+    let mut draining = false;
+
     let mut reliable_seq: HashMap<Option<u8>, u16> = Default::default();
     let mut unreliable_seq: HashMap<Option<u8>, u16> = Default::default();
 
     let stop = Arc::new(AtomicBool::new(false));
 
-    let mut next_flush = Instant::now() + Duration::from_millis(1);
+    let mut next_flush = Instant::now() + flush_interval;
 
     //
 
     while let Some(job) = next_job(
         &mut recv,
         &mut should_stop,
+        &mut drain,
         &mut next_flush,
+        flush_interval,
         can_flush,
         stop.clone(),
     )
@@ -53,21 +70,28 @@ pub async fn writer_worker_job(
             // reliable ordered packets
             WriterJob::Feed(Packet {
                 bytes,
+                compression,
                 header: PacketHeader::Ordered { stream_id },
             }) => {
                 // get old/new stream asynchronously
                 let stream = get_stream(&mut ordered, &connection, stream_id);
 
                 // encode the packet
-                let bytes = encode_packet(bytes, PacketHeader::Ordered { stream_id });
+                let bytes = encode_packet(
+                    &compressor,
+                    bytes,
+                    compression,
+                    PacketHeader::Ordered { stream_id },
+                );
 
                 // send the packet
-                send_ordered(stream.await, &mut can_flush, bytes, stop.clone()).await;
+                send_ordered(stream.await, &mut can_flush, bytes, &stop, &closed).await;
             }
 
             // reliable sequenced packets
             WriterJob::Feed(Packet {
                 bytes,
+                compression,
                 header: PacketHeader::ReliableSequenced { stream_id, .. },
             }) => {
                 // get old/new stream asynchronously
@@ -79,28 +103,45 @@ pub async fn writer_worker_job(
                 *s = s.wrapping_add(1);
 
                 // encode the packet
-                let bytes =
-                    encode_packet(bytes, PacketHeader::ReliableSequenced { stream_id, seq_id });
+                let bytes = encode_packet(
+                    &compressor,
+                    bytes,
+                    compression,
+                    PacketHeader::ReliableSequenced { stream_id, seq_id },
+                );
 
                 // send the packet
-                send_ordered(stream.await, &mut can_flush, bytes, stop.clone()).await;
+                send_ordered(stream.await, &mut can_flush, bytes, &stop, &closed).await;
             }
 
             // reliable unordered packets
             WriterJob::Feed(Packet {
                 bytes,
+                compression,
                 header: PacketHeader::ReliableUnordered,
             }) => {
                 // encode the packet
-                let bytes = encode_packet(bytes, PacketHeader::ReliableUnordered);
+                let bytes = encode_packet(
+                    &compressor,
+                    bytes,
+                    compression,
+                    PacketHeader::ReliableUnordered,
+                );
 
                 // send the packet
-                send_unordered(&connection, bytes, stop.clone());
+                send_unordered(
+                    &connection,
+                    bytes,
+                    stop.clone(),
+                    closed.clone(),
+                    unordered.clone(),
+                );
             }
 
             // unreliable sequenced packets
             WriterJob::Feed(Packet {
                 bytes,
+                compression,
                 header: PacketHeader::UnreliableSequenced { stream_id, .. },
             }) => {
                 // generate seq id
@@ -110,41 +151,75 @@ pub async fn writer_worker_job(
 
                 // encode the packet
                 let bytes = encode_packet(
+                    &compressor,
                     bytes,
+                    compression,
                     PacketHeader::UnreliableSequenced { stream_id, seq_id },
                 );
 
-                unwrap_or!(connection.send_datagram(bytes), {
-                    break;
-                });
+                unwrap_or!(
+                    connection
+                        .send_datagram(bytes)
+                        .map_err(|err| failed(err, &stop, &closed)),
+                    {
+                        break;
+                    }
+                );
             }
 
             // unreliable packets
             WriterJob::Feed(Packet {
                 bytes,
+                compression,
                 header: PacketHeader::Unreliable,
             }) => {
                 // encode the packet
-                let bytes = encode_packet(bytes, PacketHeader::Unreliable);
-
-                unwrap_or!(connection.send_datagram(bytes), {
-                    break;
-                });
+                let bytes =
+                    encode_packet(&compressor, bytes, compression, PacketHeader::Unreliable);
+
+                unwrap_or!(
+                    connection
+                        .send_datagram(bytes)
+                        .map_err(|err| failed(err, &stop, &closed)),
+                    {
+                        break;
+                    }
+                );
             }
 
             WriterJob::Flush => flush(&mut ordered, &mut can_flush).await,
+
+            // send everything that is still queued
+            // and stop when the queue is empty
+            WriterJob::Drain => {
+                drain = None;
+                recv.close();
+                draining = true;
+            }
         }
     }
 
     flush(&mut ordered, &mut can_flush).await;
 
+    if draining && !stop.load(Ordering::SeqCst) {
+        // wait for the peer to receive everything
+        drop(unordered);
+        join_all(ordered.into_values().map(|stream| async move {
+            unwrap_or!(stream.into_inner().finish().await, {});
+        }))
+        .await;
+        let _ = unordered_done.recv().await;
+    }
+
     log::debug!("Writer worker stopped");
 }
 
 async fn next_job(
     recv: &mut mpsc::Receiver<Packet>,
     should_stop: &mut broadcast::Receiver<()>,
+    drain: &mut Option<oneshot::Receiver<()>>,
     next_flush: &mut Instant,
+    flush_interval: Duration,
     can_flush: bool,
     stop: Arc<AtomicBool>,
 ) -> Option<WriterJob> {
@@ -160,13 +235,23 @@ async fn next_job(
         if can_flush {
             // log::debug!("flushing");
             sleep_until(*next_flush).await;
-            *next_flush = Instant::now() + Duration::from_millis(1);
+            *next_flush = Instant::now() + flush_interval;
         } else {
             pending::<()>().await;
         }
     };
 
+    let wait_until_drain = async {
+        if let Some(signal) = drain.as_mut() {
+            if signal.await.is_ok() {
+                return;
+            }
+        }
+        pending::<()>().await
+    };
+
     select_biased! {
+        _ = wait_until_drain.fuse() => Some(WriterJob::Drain),
         _ = wait_until_flush.fuse() => Some(WriterJob::Flush),
         p = recv.recv().fuse() => p.map(WriterJob::Feed),
         _ = should_stop.recv().fuse() => None,
@@ -179,81 +264,136 @@ async fn flush(ordered: &mut HashMap<Option<u8>, FWrite>, can_flush: &mut bool)
     // finish all pending streams
 
     join_all(ordered.iter_mut().map(|(_, stream)| async move {
-        unwrap_or!(stream.flush().await, return);
+        unwrap_or!(stream.flush().await, {});
         // unwrap_or!(stream.get_mut().finish().await, return);
     }))
     .await;
 }
 
-fn encode_packet(bytes: Bytes, header: PacketHeader) -> Bytes {
-    let mut packet = BytesMut::new().writer();
-    bincode::serialize_into(&mut packet, &Packet { header, bytes }).unwrap();
-    packet.into_inner().into()
+fn encode_packet(
+    compressor: &Compressor,
+    bytes: Bytes,
+    compression: Option<Compression>,
+    header: PacketHeader,
+) -> Bytes {
+    compressor.encode(&Packet {
+        header,
+        bytes,
+        compression,
+    })
 }
 
 async fn get_stream<'a>(
     streams: &'a mut HashMap<Option<u8>, FWrite>,
     connection: &'a Connection,
     stream: Option<u8>,
-) -> Option<&'a mut FWrite> {
+) -> Result<&'a mut FWrite, ConnectionError> {
     match streams.entry(stream) {
-        Entry::Occupied(entry) => Some(entry.into_mut()),
-        Entry::Vacant(entry) => Some(entry.insert(FramedWrite::new(
-            unwrap_or!(connection.open_uni().await, return None),
+        Entry::Occupied(entry) => Ok(entry.into_mut()),
+        Entry::Vacant(entry) => Ok(entry.insert(FramedWrite::new(
+            connection.open_uni().await?,
             LengthDelimitedCodec::default(),
         ))),
     }
 }
 
 async fn send_ordered(
-    stream: Option<&mut FWrite>,
+    stream: Result<&mut FWrite, ConnectionError>,
     can_flush: &mut bool,
     bytes: Bytes,
-    stop: Arc<AtomicBool>,
+    stop: &AtomicBool,
+    closed: &Closed,
 ) {
     // get the stream
-    let stream = unwrap_or!(stream.ok_or_else(|| "Missing stream".to_owned()), {
-        stop.store(true, Ordering::SeqCst);
+    let stream = unwrap_or!(stream.map_err(|err| failed(err, stop, closed)), {
         return;
     });
 
     // feed to it
-    unwrap_or!(stream.feed(bytes).await, {
-        stop.store(true, Ordering::SeqCst);
-        return;
-    });
+    unwrap_or!(
+        stream
+            .feed(bytes)
+            .await
+            .map_err(|err| failed(err, stop, closed)),
+        {
+            return;
+        }
+    );
 
     *can_flush = true;
 }
 
-fn send_unordered(connection: &Connection, bytes: Bytes, stop: Arc<AtomicBool>) {
+fn send_unordered(
+    connection: &Connection,
+    bytes: Bytes,
+    stop: Arc<AtomicBool>,
+    closed: Closed,
+    done: mpsc::Sender<()>,
+) {
     let open_uni = connection.open_uni();
     tokio::spawn(async move {
+        let _done = done;
+
         // get a new stream
-        let stream = unwrap_or!(open_uni.await, {
-            stop.store(true, Ordering::SeqCst);
+        let stream = unwrap_or!(open_uni.await.map_err(|err| failed(err, &stop, &closed)), {
             return;
         });
         let mut stream = FramedWrite::new(stream, LengthDelimitedCodec::default());
 
         // send with it
-        unwrap_or!(stream.send(bytes).await, {
-            stop.store(true, Ordering::SeqCst);
-            return;
-        });
+        unwrap_or!(
+            stream
+                .send(bytes)
+                .await
+                .map_err(|err| failed(err, &stop, &closed)),
+            {
+                return;
+            }
+        );
 
         // flush it
-        unwrap_or!(stream.get_mut().finish().await, {
-            stop.store(true, Ordering::SeqCst);
-        });
+        unwrap_or!(
+            stream
+                .get_mut()
+                .finish()
+                .await
+                .map_err(|err| failed(err, &stop, &closed)),
+            {}
+        );
     });
 }
 
+// stops the writer and records why,
+// errors other than the connection being
+// lost also close the connection
+fn failed<E: Error + 'static>(err: E, stop: &AtomicBool, closed: &Closed) -> E {
+    stop.store(true, Ordering::SeqCst);
+    match connection_lost(&err) {
+        Some(lost) => closed.lost(lost),
+        None => closed.fail(DisconnectReason::WriteFailed(err.to_string())),
+    }
+    err
+}
+
+fn connection_lost<'a>(err: &'a (dyn Error + 'static)) -> Option<&'a ConnectionError> {
+    if let Some(err) = err.downcast_ref::<io::Error>() {
+        return connection_lost(err.get_ref()?);
+    }
+
+    match (err.downcast_ref(), err.downcast_ref(), err.downcast_ref()) {
+        (Some(err), _, _) => Some(err),
+        (_, Some(WriteError::ConnectionLost(err)), _) => Some(err),
+        (_, _, Some(SendDatagramError::ConnectionLost(err))) => Some(err),
+        _ => None,
+    }
+}
+
 //
 
 enum WriterJob {
     Feed(Packet),
     Flush,
+    Drain,
 }
 
 type FWrite = FramedWrite<SendStream, LengthDelimitedCodec>;